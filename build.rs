@@ -1,22 +1,213 @@
-use std::{fs::File, io::Write};
+use std::{collections::HashMap, env, fmt::Write as _, fs, path::{Path, PathBuf}};
 
 use gimura_preprocessor_lib::prelude::*;
 
+#[allow(dead_code)]
+#[path = "src/uniforms.rs"]
+mod uniforms;
+
+use uniforms::*;
+
 fn main() {
     println!("cargo::rerun-if-changed=./examples/example_shaders/");
-    println!("cargo::rerun-if-changed=./shaders_std/");
+    println!("cargo::rerun-if-changed=./shader_std/");
+    println!("cargo::rerun-if-changed=./src/uniforms.rs");
+
+    // WGSL side of every struct in src/uniforms.rs, included by the std
+    // sources as "generated" "uniforms.wgsl".
+    let generated = [
+        "// Generated by build.rs from src/uniforms.rs, do not edit.\n".to_string(),
+        format!("const TREE_STACK_SIZE: u32 = {}u;\n", TREE_STACK_SIZE),
+        MetaDataUniformRaw::wgsl_struct(),
+        RenderSettingsRaw::wgsl_struct(),
+        CameraUniform::wgsl_struct(),
+        MaterialUniform::wgsl_struct(),
+        CompiledUniform::wgsl_struct(),
+        VoxelSpaceUniform::wgsl_struct(),
+        VoxelInstanceUniform::wgsl_struct(),
+        TlasNodeUniform::wgsl_struct(),
+    ].join("\n");
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not set!");
+
+    fs::write(Path::new(&out_dir).join("uniforms.wgsl"), &generated)
+        .expect("Error to write generated uniforms!");
+
+    embed_shader_std(Path::new(&out_dir));
 
     let preprocessor_options = PreprocessorOptions::default();
     let mut preprocessor = Preporcessor::new(preprocessor_options);
     
     preprocessor.add_source("main".to_string(), CodeSource::from_path("./examples/example_shaders/".to_string()));
     preprocessor.add_source("std".to_string(), CodeSource::from_path("./shader_std/".to_string()));
+    preprocessor.add_source("generated".to_string(), CodeSource::new(HashMap::from([
+        ("uniforms.wgsl".to_string(), generated)
+    ])));
 
     let source = preprocessor.preprocess("main".to_string(), "main.wgsl".to_string());
 
-    let mut file = File::create("target/compiled.wgsl")
-        .expect("Error to open file!");
+    check_layouts(&source);
+
+    // Only kept for inspecting the layout checked above, nothing reads it.
+    fs::write(Path::new(&out_dir).join("compiled.wgsl"), &source)
+        .expect("Error to write compiled shader!");
+}
+
+
+// Writes the list of shader_std files as `(path, source)` pairs with the paths
+// relative to shader_std, so `ShaderBuilder` can carry the library in the crate.
+fn embed_shader_std(out_dir: &Path) {
+    let root = Path::new(&env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set!")).join("shader_std");
+
+    let mut files = vec![];
+    collect_files(&root, &mut files);
+    files.sort();
+
+    let mut out = "&[\n".to_string();
+
+    for file in files {
+        let path = file.strip_prefix(&root).unwrap().to_str().unwrap().replace('\\', "/");
+
+        let _ = writeln!(out, "    ({:?}, include_str!({:?})),", path, file.to_str().unwrap());
+    }
+
+    out.push(']');
+
+    fs::write(out_dir.join("shader_std.rs"), out)
+        .expect("Error to write embedded shader_std!");
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).expect("Error to read shader_std!") {
+        let path = entry.expect("Error to read shader_std!").path();
+
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+struct RustLayout {
+    name: &'static str,
+    size: usize,
+    members: &'static [(&'static str, usize, usize)],
+}
+
+macro_rules! rust_layout {
+    ($ty:ty) => {
+        RustLayout {
+            name: <$ty>::WGSL_NAME,
+            size: <$ty>::WGSL_SIZE,
+            members: <$ty>::WGSL_MEMBERS,
+        }
+    };
+}
+
+// Lays out the compiled shader with naga and compares every bound struct, and
+// the structs nested in them, with the Rust serialisers member by member.
+fn check_layouts(source: &str) {
+    let module = naga::front::wgsl::parse_str(source)
+        .unwrap_or_else(|e| panic!("Error to parse compiled shader:\n{}", e.emit_to_string(source)));
+
+    let mut layouter = naga::proc::Layouter::default();
+
+    layouter.update(module.to_ctx())
+        .expect("Error to lay out compiled shader!");
+
+    let bindings = [
+        ("u_camera", rust_layout!(CameraUniform)),
+        ("u_meta_data", rust_layout!(MetaDataUniformRaw)),
+        ("u_settings", rust_layout!(RenderSettingsRaw)),
+        ("b_voxels", rust_layout!(CompiledUniform)),
+        ("u_voxel_space", rust_layout!(VoxelSpaceUniform)),
+        ("b_instances", rust_layout!(VoxelInstanceUniform)),
+        ("b_tlas", rust_layout!(TlasNodeUniform)),
+    ];
+
+    let nested = [
+        rust_layout!(MaterialUniform),
+    ];
+
+    let mut report = String::new();
+
+    for (global, layout) in &bindings {
+        let Some((_, var)) = module.global_variables.iter().find(|(_, var)| var.name.as_deref() == Some(global)) else {
+            let _ = writeln!(report, "`{}`: not bound in WGSL, expected `{}`", global, layout.name);
+            continue;
+        };
+
+        let mut ty = var.ty;
+
+        if let naga::TypeInner::Array { base, stride, .. } = module.types[ty].inner {
+            if stride as usize != layout.size {
+                let _ = writeln!(report, "`{}`: array stride {} in WGSL, {} in Rust", global, stride, layout.size);
+            }
+
+            ty = base;
+        }
+
+        compare_struct(&module, &layouter, ty, layout, global, &mut report);
+    }
+
+    for layout in &nested {
+        let Some((ty, _)) = module.types.iter().find(|(_, ty)| ty.name.as_deref() == Some(layout.name)) else {
+            let _ = writeln!(report, "`{}`: not declared in WGSL", layout.name);
+            continue;
+        };
+
+        compare_struct(&module, &layouter, ty, layout, layout.name, &mut report);
+    }
+
+    if !report.is_empty() {
+        panic!("WGSL layouts do not match src/uniforms.rs:\n{}", report);
+    }
+}
+
+fn compare_struct(
+    module: &naga::Module,
+    layouter: &naga::proc::Layouter,
+    ty: naga::Handle<naga::Type>,
+    layout: &RustLayout,
+    context: &str,
+    report: &mut String,
+) {
+    let ty = &module.types[ty];
+
+    let naga::TypeInner::Struct { members, span } = &ty.inner else {
+        let _ = writeln!(report, "`{}`: not a struct in WGSL, `{}` in Rust", context, layout.name);
+        return;
+    };
+
+    let name = ty.name.as_deref().unwrap_or("?");
+
+    let mut lines = vec![];
+    let mut matches = name == layout.name && *span as usize == layout.size && members.len() == layout.members.len();
+
+    lines.push(format!("struct `{}` size {} | Rust `{}` size {}", name, span, layout.name, layout.size));
+
+    for i in 0..members.len().max(layout.members.len()) {
+        let wgsl = members.get(i)
+            .map(|member| (member.name.as_deref().unwrap_or("?"), member.offset as usize, layouter[member.ty].size as usize));
+        let rust = layout.members.get(i).copied();
+
+        let describe = |member: Option<(&str, usize, usize)>| match member {
+            Some((name, offset, size)) => format!("{} at {} size {}", name, offset, size),
+            None => "-".to_string(),
+        };
+
+        let same = wgsl == rust;
+        matches &= same;
+
+        lines.push(format!("{} {} | {}", if same { "   " } else { "!!!" }, describe(wgsl), describe(rust)));
+    }
+
+    if !matches {
+        let _ = writeln!(report, "`{}`:", context);
 
-    file.write_all(source.as_bytes())
-        .expect("Error to write file!");
-}
\ No newline at end of file
+        for line in lines {
+            let _ = writeln!(report, "    {}", line);
+        }
+    }
+}
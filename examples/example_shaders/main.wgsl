@@ -1,24 +1,43 @@
 //! include "std" "std.wgsl"
 
-//! define SAMPLE_COUNT "10"
-
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let uv = in.uv * u_meta_data.res / u_meta_data.res.y;
+// `position` is in pixels from the top left corner, like the fragment position.
+fn render_pixel(position: vec2<f32>) -> vec3<f32> {
+    let ndc = (position / u_meta_data.res * 2.0 - 1.0) * vec2<f32>(1.0, -1.0);
+    let uv = ndc * u_meta_data.res / u_meta_data.res.y;
     
     var ray_orig = u_camera.pos.xyz;
     let ray_dir = (normalize(vec3<f32>(1.0, uv)) * u_camera.matrix).xyz;
 
-    // var color = get_color_with_ray_casting(ray_orig, ray_dir);
+    if u_settings.mode == TRACE_MODE_RAY_CASTING {
+        return get_color_with_ray_casting(ray_orig, ray_dir);
+    }
+
+    let samples = max(u_settings.samples, 1u);
 
     var color = vec3<f32>(0.0);
 
-    for (var sample = 0; sample < SAMPLE_COUNT; sample++) {
-        let tmp_color = trace_ray(ray_orig, ray_dir, uv + f32(sample) * 23.00231);
+    for (var sample = 0u; sample < samples; sample++) {
+        seed_rand(vec2<u32>(position), u_meta_data.time, sample, samples);
+
+        let tmp_color = trace_ray(ray_orig, ray_dir);
         color += tmp_color;
     }
 
-    color /= f32(SAMPLE_COUNT);
+    color /= f32(samples);
+
+    return color;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    return accumulate(in.clip_position, render_pixel(in.clip_position.xy));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if f32(id.x) >= u_meta_data.res.x || f32(id.y) >= u_meta_data.res.y {
+        return;
+    }
 
-    return vec4<f32>(color, 1.0);
+    accumulate_store(id.xy, render_pixel(vec2<f32>(id.xy) + 0.5));
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+use fast_voxel_rs::*;
+use fast_voxel_rs::render::*;
+
+// Usage: headless [--fallback] [--compute] [--frames <count>] [--size <width>x<height>] <output.png|output.ppm>
+//
+// WGPU_BACKEND selects the backend, e.g. WGPU_BACKEND=gl for a Mesa software
+// rasterizer on machines without a GPU.
+fn main() {
+    let env = env_logger::Env::new().filter_or("RUST_LOG", "fast_voxel_rust=trace,fast_voxel_lib=trace,wgpu=warn");
+    env_logger::init_from_env(env);
+
+    let mut force_fallback_adapter = false;
+    let mut mode = RenderMode::Fragment;
+    let mut frames = 1;
+    let mut size = (800, 600);
+    let mut output = None;
+
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fallback" => force_fallback_adapter = true,
+            "--compute" => mode = RenderMode::Compute,
+            "--frames" => {
+                frames = args.next().expect("--frames needs a value")
+                    .parse().expect("Invalid frame count!");
+            },
+            "--size" => {
+                let value = args.next().expect("--size needs a value");
+                let (width, height) = value.split_once('x').expect("size must look like 800x600");
+
+                size = (
+                    width.parse().expect("Invalid width!"),
+                    height.parse().expect("Invalid height!")
+                );
+            },
+            _ => output = Some(arg),
+        }
+    }
+
+    let output = output.unwrap_or_else(|| "render.png".to_string());
+
+    let app = pollster::block_on(App::new_headless(AppDescriptor { force_fallback_adapter }, size.0, size.1));
+    let mut render = app.create_render(RenderCreateDescriptor {
+        shader: ShaderBuilder::new(include_str!("../example_shaders/main.wgsl"))
+            .build(&app)
+            .unwrap_or_else(|e| panic!("Error to build shader: {}", e)),
+        camera: (
+            (-10.0, 0.0, 0.0).into(),
+            (0.0, 0.0, 0.0).into()
+        ),
+        mode
+    });
+
+    // Every frame adds to the accumulated average, only the last one is kept.
+    let mut image = render.render_image(&app)
+        .expect("Error to read back the frame!");
+
+    for _ in 1..frames {
+        image = render.render_image(&app)
+            .expect("Error to read back the frame!");
+    }
+
+    image.save(&output)
+        .expect("Error to write image!");
+
+    log::info!("Frame saved to {}", output);
+}
@@ -0,0 +1,39 @@
+use std::{env, fs, process};
+
+use fast_voxel_rs::voxel::{find_root, scene::parse_scene, simplify::simplify, svo::write_svo};
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    let compress = args.iter().any(|arg| arg == "--compress");
+    let paths = args.iter().filter(|arg| !arg.starts_with("--")).collect::<Vec<_>>();
+
+    if paths.len() != 2 {
+        eprintln!("usage: json2svo [--compress] <scene.json> <scene.svo>");
+        process::exit(1);
+    }
+
+    let source = fs::read_to_string(paths[0]).unwrap_or_else(|e| {
+        eprintln!("Error to read {}: {}", paths[0], e);
+        process::exit(1);
+    });
+
+    let nodes = parse_scene(source.as_str()).unwrap_or_else(|e| {
+        eprintln!("Error to load scene file: {}", e);
+        process::exit(1);
+    });
+
+    let nodes = match find_root(&nodes) {
+        Some(root) => simplify(&nodes, root),
+        None => nodes,
+    };
+
+    let root = nodes.len().saturating_sub(1) as u32;
+
+    if let Err(e) = write_svo(paths[1].to_string(), &nodes, root, compress) {
+        eprintln!("Error to write {}: {}", paths[1], e);
+        process::exit(1);
+    }
+
+    println!("{}: {} nodes, root {}", paths[1], nodes.len(), root);
+}
@@ -21,16 +21,20 @@ pub async fn run() {
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
         
-    let app = App::new(AppDescriptor {  }, &window).await;
-    let render = app.create_render(RenderCreateDescriptor {
-        shader: app.create_shader(&ShaderCreateDescriptor {
-            shdaer_source: include_str!("../../target/compiled.wgsl").to_string()
-        }),
+    let app = App::new(AppDescriptor::default(), &window).await;
+    let mut render = app.create_render(RenderCreateDescriptor {
+        shader: ShaderBuilder::new(include_str!("../example_shaders/main.wgsl"))
+            .build(&app)
+            .unwrap_or_else(|e| panic!("Error to build shader: {}", e)),
         camera: (
             (-10.0, 0.0, 0.0).into(),
             (0.0, 0.0, 0.0).into()
-        )
+        ),
+        mode: RenderMode::Fragment
     }); 
+
+    #[cfg(not(target_arch = "wasm32"))]
+    render.watch_shader("examples/example_shaders", "shader_std");
         
     app.run(render, event_loop);
 } 
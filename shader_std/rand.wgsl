@@ -3,8 +3,41 @@
 
 //! include "std" "header.wgsl"
 
+// Generator behind `rand`: rand_taus, rand_pcg or rand_r2. Define it before
+// including std to pick another one.
+//! ifndef RAND_GENERATOR
+//! define RAND_GENERATOR "rand_taus"
+//! endif
+
 var<private> rand_state: vec4<u32>;
 
+// Index of the sample, dimension of the next number and per-pixel shift of
+// the low-discrepancy sequence.
+var<private> sequence_state: vec3<u32>;
+
+fn pcg_hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+
+    return (word >> 22u) ^ word;
+}
+
+// Must be called before the first `rand` of every sample, every pixel, frame
+// and sample gets its own sequence.
+fn seed_rand(pixel: vec2<u32>, frame: u32, sample: u32, sample_count: u32) {
+    let pixel_seed = pcg_hash(pixel.x + pcg_hash(pixel.y));
+    let index = frame * sample_count + sample;
+    let seed = pcg_hash(pixel_seed ^ pcg_hash(index));
+
+    // Tausworthe steps need states above 128, PCG only uses `x`.
+    rand_state.x = max(seed, 128u);
+    rand_state.y = max(pcg_hash(seed ^ 1u), 128u);
+    rand_state.z = max(pcg_hash(seed ^ 2u), 128u);
+    rand_state.w = pcg_hash(seed ^ 3u);
+
+    sequence_state = vec3<u32>(index, 0u, pixel_seed);
+}
+
 fn taus_step(z: u32, s1: u32, s2: u32, s3: u32, m: u32) -> u32 {
     let b = (((z << s1) ^ z) >> s2);
     return (((z & m) << s3) ^ b);
@@ -14,7 +47,7 @@ fn lcg_step(z: u32, a: u32, c: u32) -> u32 {
     return (a * z + c);
 }
 
-fn rand() -> f32 {
+fn rand_taus() -> f32 {
     rand_state.x = taus_step(rand_state.x, u32(13), u32(19), u32(12), u32(4294967294));
     rand_state.y = taus_step(rand_state.y, u32(2), u32(25), u32(4), u32(4294967288));
     rand_state.z = taus_step(rand_state.z, u32(3), u32(11), u32(17), u32(4294967280));
@@ -23,6 +56,32 @@ fn rand() -> f32 {
     return 2.3283064365387e-10 * f32((rand_state.x ^ rand_state.y ^ rand_state.z ^ rand_state.w));
 }
 
+fn rand_pcg() -> f32 {
+    rand_state.x = rand_state.x * 747796405u + 2891336453u;
+
+    let state = rand_state.x;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+
+    return 2.3283064365387e-10 * f32((word >> 22u) ^ word);
+}
+
+// R2 sequence (Roberts 2018), consecutive calls alternate between its two
+// axes. Every pair gets its own random shift so the dimensions of one path
+// don't correlate, the step constants are the R2 alphas in 0.32 fixed point.
+fn rand_r2() -> f32 {
+    let dimension = sequence_state.y;
+    sequence_state.y += 1u;
+
+    let shift = pcg_hash(sequence_state.z ^ pcg_hash(dimension / 2u));
+    let alpha = select(3242174889u, 2447445413u, dimension % 2u == 1u);
+
+    return 2.3283064365387e-10 * f32(shift + alpha * sequence_state.x);
+}
+
+fn rand() -> f32 {
+    return RAND_GENERATOR();
+}
+
 fn rand2() -> vec2<f32> {
     return vec2<f32>(rand(), rand());
 }
@@ -35,4 +94,4 @@ fn rand4() -> vec4<f32> {
     return vec4<f32>(rand(), rand(), rand(), rand());
 }
 
-//! endif
\ No newline at end of file
+//! endif
@@ -0,0 +1,36 @@
+//! ifndef _render_accumulation_wgsl
+//! define _render_accumulation_wgsl ""
+
+//! include "std" "uniforms.wgsl"
+
+// Blends the color of this frame into the running average of the previous
+// ones. `frame` is zero after a reset, which drops the old history.
+fn accumulated(pixel: vec2<u32>, color: vec3<f32>) -> vec3<f32> {
+    let history = textureLoad(t_accumulation, pixel, 0).rgb;
+
+    return mix(history, color, 1.0 / f32(u_meta_data.frame + 1u));
+}
+
+// Exposure only scales what is shown, the history stays unexposed so changing
+// it doesn't restart the average.
+fn exposed(color: vec3<f32>) -> vec3<f32> {
+    return color * u_settings.exposure;
+}
+
+fn accumulate(position: vec4<f32>, color: vec3<f32>) -> FragmentOutput {
+    var out: FragmentOutput;
+
+    let average = accumulated(vec2<u32>(position.xy), color);
+
+    out.color = vec4<f32>(exposed(average), 1.0);
+    out.accumulation = vec4<f32>(average, 1.0);
+
+    return out;
+}
+
+// Compute path version of `accumulate`, `fs_blit` shows the result.
+fn accumulate_store(pixel: vec2<u32>, color: vec3<f32>) {
+    textureStore(t_output, pixel, vec4<f32>(accumulated(pixel, color), 1.0));
+}
+
+//! endif
\ No newline at end of file
@@ -0,0 +1,13 @@
+//! ifndef _render_blit_wgsl
+//! define _render_blit_wgsl ""
+
+//! include "std" "uniforms.wgsl"
+//! include "std" "accumulation.wgsl"
+
+// Copies the image traced by the compute path to the surface.
+@fragment
+fn fs_blit(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(exposed(textureLoad(t_accumulation, vec2<u32>(in.clip_position.xy), 0).rgb), 1.0);
+}
+
+//! endif
\ No newline at end of file
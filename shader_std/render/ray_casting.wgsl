@@ -4,52 +4,149 @@
 //! include "std" "render_def.wgsl"
 //! include "std" "uniforms.wgsl"
 
+//! ifndef FAR_DISTANCE
 //! define FAR_DISTANCE "1000000.0"
-
-fn cast_ray(ro: vec3<f32>, rd: vec3<f32>) -> IntersectInfo {
+//! endif
+//! define TLAS_STACK_SIZE "32u"
+//! define TLAS_NO_OBJECT "4294967295u"
+
+// Rays are moved into the space of the instance without normalizing the
+// direction, so the hit fraction stays valid in world space.
+//
+// Nodes are visited front to back: the intersected children of a branch are
+// sorted by entry distance and pushed far to near, so the nearest one is
+// popped first and the others are still there if it misses. Anything that
+// starts behind the closest hit found so far is skipped.
+fn cast_ray_tree(instance: VoxelInstance, _ro: vec3<f32>, _rd: vec3<f32>) -> IntersectInfo {
     var out: IntersectInfo;
-    var min_dist = FAR_DISTANCE;
+    out.is_intersected = false;
+    out.fraction = FAR_DISTANCE;
+
+    if instance.node_count == 0u {
+        return out;
+    }
+
+    let ro = instance.rotation * (_ro - instance.position) / instance.scale;
+    let rd = instance.rotation * _rd / instance.scale;
+
+    var stack: array<u32, TREE_STACK_SIZE>;
+    var stack_fraction: array<f32, TREE_STACK_SIZE>;
+    var stack_size = 1u;
 
-    var box = b_voxels[8];
+    stack[0] = instance.root;
+    stack_fraction[0] = -FAR_DISTANCE;
 
-    while true {
+    while stack_size > 0u {
+        stack_size -= 1u;
+
+        if stack_fraction[stack_size] >= out.fraction {
+            continue;
+        }
+
+        // `var` so `childs` can be indexed with a runtime value.
+        var box = get_voxel(instance.node_offset + stack[stack_size]);
         let int = box_int(ro, rd, box);
 
-        if !int.is_intersected {
-            out = int;
-            break;
+        if !int.is_intersected || int.fraction >= out.fraction || box.is_none >= 1.0 {
+            continue;
         }
 
         if box.is_leaf >= 1.0 {
             out = int;
             out.material = box.material;
 
-            break;
-        } else {
-            var child_min_dist = FAR_DISTANCE;
-            var box_out = box;
+            continue;
+        }
+
+        var childs: array<u32, 8>;
+        var fractions: array<f32, 8>;
+        var count = 0u;
 
-            for (var i = 0; i < 8; i++) {
-                let child_box = get_voxel(u32(box.childs[i]));
-                let child_int = box_int(ro, rd, child_box);
+        for (var i = 0; i < 8; i++) {
+            let child = u32(box.childs[i]);
+            let child_box = get_voxel(instance.node_offset + child);
+            let child_int = box_int(ro, rd, child_box);
 
-                if child_int.is_intersected && child_int.fraction < child_min_dist && child_box.is_none != 1.0 {
-                    child_min_dist = child_int.fraction;
-                    box_out = child_box;
-                }
+            if !child_int.is_intersected || child_int.fraction >= out.fraction || child_box.is_none >= 1.0 {
+                continue;
             }
-            
-            box = box_out;
 
-            if child_min_dist == FAR_DISTANCE {
-                out.is_intersected = false;
-                break;
+            // Insertion sort, farthest first.
+            var j = count;
+
+            while j > 0u && fractions[j - 1u] < child_int.fraction {
+                childs[j] = childs[j - 1u];
+                fractions[j] = fractions[j - 1u];
+                j -= 1u;
             }
 
+            childs[j] = child;
+            fractions[j] = child_int.fraction;
+            count += 1u;
+        }
+
+        for (var i = 0u; i < count && stack_size < TREE_STACK_SIZE; i++) {
+            stack[stack_size] = childs[i];
+            stack_fraction[stack_size] = fractions[i];
+            stack_size += 1u;
+        }
+    }
+
+    if out.is_intersected {
+        out.normal = transpose(instance.rotation) * out.normal;
+    }
+
+    return out;
+}
+
+fn aabb_int(ro: vec3<f32>, inv_rd: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>) -> f32 {
+    let t1 = (box_min - ro) * inv_rd;
+    let t2 = (box_max - ro) * inv_rd;
+
+    let tN = max(max(min(t1.x, t2.x), min(t1.y, t2.y)), min(t1.z, t2.z));
+    let tF = min(min(max(t1.x, t2.x), max(t1.y, t2.y)), max(t1.z, t2.z));
+
+    if tN > tF || tF < 0.0 {
+        return FAR_DISTANCE;
+    }
+
+    return tN;
+}
+
+fn cast_ray(ro: vec3<f32>, rd: vec3<f32>) -> IntersectInfo {
+    var out: IntersectInfo;
+    out.is_intersected = false;
+    out.fraction = FAR_DISTANCE;
+
+    if u_voxel_space.tlas_node_count == 0u {
+        return out;
+    }
+
+    let inv_rd = vec3<f32>(1.0) / rd;
+
+    var stack: array<u32, TLAS_STACK_SIZE>;
+    var stack_size = 1u;
+    stack[0] = 0u;
+
+    while stack_size > 0u {
+        stack_size -= 1u;
+        let node = b_tlas[stack[stack_size]];
+
+        if aabb_int(ro, inv_rd, node.min, node.max) >= out.fraction {
             continue;
         }
 
-        break;
+        if node.object != TLAS_NO_OBJECT {
+            let int = cast_ray_tree(b_instances[node.object], ro, rd);
+
+            if int.is_intersected && int.fraction < out.fraction {
+                out = int;
+            }
+        } else if stack_size + 2u <= TLAS_STACK_SIZE {
+            stack[stack_size] = node.left;
+            stack[stack_size + 1u] = node.left + 1u;
+            stack_size += 2u;
+        }
     }
 
     return out;
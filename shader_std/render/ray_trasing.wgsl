@@ -1,8 +1,6 @@
 //! ifndef _render_ray_trasing_wgsl
 //! define _render_ray_trasing_wgsl ""
 
-//! define MAX_DEPTH "4"
-
 //! include "std" "render_def.wgsl"
 //! include "std" "ray_casting.wgsl"
 //! include "std" "math.wgsl"
@@ -32,14 +30,14 @@ fn normal_point(n: vec3<f32>) -> vec3<f32> {
     return v;
 }
 
-fn trace_ray(_ro: vec3<f32>, _rd: vec3<f32>, uv: vec2<f32>) -> vec3<f32> {
+fn trace_ray(_ro: vec3<f32>, _rd: vec3<f32>) -> vec3<f32> {
     var L = vec3<f32>(0.0);
     var F = vec3<f32>(1.0);
 
     var ro = _ro;
     var rd = _rd;
 
-    for (var i = 0; i < MAX_DEPTH; i++) {
+    for (var i = 0u; i < u_settings.max_bounces; i++) {
         let hit = cast_ray(ro, rd);
 
         if hit.is_intersected {
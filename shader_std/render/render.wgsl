@@ -4,5 +4,7 @@
 //! include "std" "render_def.wgsl"
 //! include "std" "ray_casting.wgsl"
 //! include "std" "ray_trasing.wgsl"
+//! include "std" "accumulation.wgsl"
+//! include "std" "blit.wgsl"
 
 //! endif
\ No newline at end of file
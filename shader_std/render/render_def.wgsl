@@ -1,24 +1,6 @@
 //! ifndef _render_def_wgsl
 //! define _render_def_wgsl ""
 
-struct Cube {
-    position: vec3<f32>,
-    rotation: mat3x3<f32>,
-    size: f32,
-
-    material: Material,
-    childs: array<f32, 8>,
-    is_leaf: f32,
-    is_none: f32
-}
-
-struct Material {
-    emmitance: vec3<f32>,
-    reflectance: vec3<f32>,
-    roughness: f32,
-    opacity: f32
-}
-
 struct IntersectInfo {
     is_intersected: bool,
     fraction: f32,
@@ -1,6 +1,12 @@
 //! ifndef _uniforms_wgsl
 //! define _uniforms_wgsl ""
 
+//! include "generated" "uniforms.wgsl"
+
+// `RenderSettings::mode`, must match `TraceMode`.
+const TRACE_MODE_PATH_TRACING: u32 = 0u;
+const TRACE_MODE_RAY_CASTING: u32 = 1u;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
 };
@@ -10,21 +16,22 @@ struct VertexOutput {
     @location(0) uv: vec2<f32>
 };
 
-struct MetaDataUniform {
-    res: vec2<f32>,
-    time: u32
-}
-
-struct CameraUniform {
-    matrix: mat3x3<f32>,
-    pos: vec3<f32>,
-}
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) accumulation: vec4<f32>
+};
 
 //! include "std" "render_def.wgsl"
 
 @group(0) @binding(0) var<uniform> u_camera: CameraUniform;
 @group(1) @binding(0) var<uniform> u_meta_data: MetaDataUniform;
+@group(1) @binding(1) var<uniform> u_settings: RenderSettings;
 @group(2) @binding(0) var<storage, read> b_voxels: array<Cube>;
+@group(2) @binding(1) var<uniform> u_voxel_space: VoxelSpaceUniform;
+@group(2) @binding(2) var<storage, read> b_instances: array<VoxelInstance>;
+@group(2) @binding(3) var<storage, read> b_tlas: array<TlasNode>;
+@group(3) @binding(0) var t_accumulation: texture_2d<f32>;
+@group(3) @binding(1) var t_output: texture_storage_2d<rgba32float, write>;
 
 fn get_voxel(i: u32) -> Cube {
     return b_voxels[i];
@@ -1,6 +1,8 @@
 pub mod render;
 pub mod voxel;
 
+mod uniforms;
+
 use std::time::Instant;
 
 use log::*;
@@ -13,9 +15,14 @@ use winit::{
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
 
+#[derive(Default)]
 pub struct AppDescriptor {
+    // Lets wgpu pick a software adapter such as llvmpipe or lavapipe.
+    pub force_fallback_adapter: bool,
+}
 
-} 
+// Format of the offscreen target in headless mode.
+const HEADLESS_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
 
 #[allow(dead_code)]
 pub struct App<'a> {
@@ -24,12 +31,12 @@ pub struct App<'a> {
     device: Device,
     queue: Queue,
 
-    surface: Surface<'a>,
+    surface: Option<Surface<'a>>,
     surface_config: SurfaceConfiguration,
 
     size: PhysicalSize<u32>,
 
-    window: &'a Window,
+    window: Option<&'a Window>,
     delta_time: f64
 }
 
@@ -55,36 +62,11 @@ impl<'a> App<'a> {
 
         let size = window.inner_size();
 
-        let instance = Instance::new(InstanceDescriptor {
-            #[cfg(not(target_arch="wasm32"))]
-            backends: Backends::PRIMARY,
-            #[cfg(target_arch="wasm32")]
-            backends: Backends::GL,
-            ..Default::default()
-        });
+        let instance = Self::create_instance();
 
         let surface = instance.create_surface(window).unwrap();
 
-        let adapter = instance.request_adapter(&RequestAdapterOptions {
-            power_preference: PowerPreference::default(),
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false
-        }).await.unwrap();
-
-        debug!("Selected device: {}", adapter.get_info().name);
-
-        let (device, queue) = adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                label: None,
-            },
-            None,
-        ).await.unwrap();
+        let (adapter, device, queue) = Self::request_device(&desc, &instance, Some(&surface)).await;
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -105,27 +87,96 @@ impl<'a> App<'a> {
         };
 
         Self {
-            window,
+            window: Some(window),
             instance,
             device,
             queue,
-            surface,
+            surface: Some(surface),
             surface_config,
             size,
             delta_time: 0.0
         }
     }
 
+    // App without a window, frames are drawn with `Render::render_image`.
+    pub async fn new_headless(desc: AppDescriptor, width: u32, height: u32) -> App<'static> {
+        let instance = Self::create_instance();
+
+        let (_, device, queue) = Self::request_device(&desc, &instance, None).await;
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: HEADLESS_FORMAT,
+            width,
+            height,
+            present_mode: PresentMode::Fifo,
+            alpha_mode: CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+
+        App {
+            window: None,
+            instance,
+            device,
+            queue,
+            surface: None,
+            surface_config,
+            size: PhysicalSize::new(width, height),
+            delta_time: 0.0
+        }
+    }
+
+    fn create_instance() -> Instance {
+        Instance::new(InstanceDescriptor {
+            #[cfg(not(target_arch="wasm32"))]
+            backends: util::backend_bits_from_env().unwrap_or(Backends::PRIMARY),
+            #[cfg(target_arch="wasm32")]
+            backends: Backends::GL,
+            ..Default::default()
+        })
+    }
+
+    async fn request_device(desc: &AppDescriptor, instance: &Instance, surface: Option<&Surface<'_>>) -> (Adapter, Device, Queue) {
+        let adapter = instance.request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::default(),
+            compatible_surface: surface,
+            force_fallback_adapter: desc.force_fallback_adapter
+        }).await.expect("No suitable graphics adapter found!");
+
+        debug!("Selected device: {}", adapter.get_info().name);
+
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: wgpu::Features::empty(),
+                required_limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                },
+                label: None,
+            },
+            None,
+        ).await.unwrap();
+
+        (adapter, device, queue)
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
     pub fn create_render(&self, desc: render::RenderCreateDescriptor) -> render::Render {
         render::Render::new(desc, &self)
     }
 
-    pub fn create_shader(&self, desc: &render::ShaderCreateDescriptor) -> render::Shader {
+    pub fn create_shader(&self, desc: &render::ShaderCreateDescriptor) -> Result<render::Shader, render::ShaderError> {
         render::Shader::new(desc, &self)
     }
 
     #[allow(unused_assignments)]
     pub fn run(mut self, mut render: render::Render, event_loop: EventLoop<()>) {
+        let window = self.window.expect("Headless app can't run an event loop!");
         let mut current_time = Instant::now();
 
         event_loop.run(move |event, control_flow| {
@@ -143,7 +194,7 @@ impl<'a> App<'a> {
                 Event::WindowEvent {
                     ref event,
                     window_id,
-                } if window_id == self.window.id() => {
+                } if window_id == window.id() => {
                     render.handle_events(event, &mut self);
 
                     match event {
@@ -162,7 +213,7 @@ impl<'a> App<'a> {
                     }
                 },
 
-                Event::AboutToWait => self.window.request_redraw(),
+                Event::AboutToWait => window.request_redraw(),
 
                 _ => {}
             }
@@ -0,0 +1,175 @@
+use wgpu::*;
+
+use crate::App;
+
+// Running average of the traced frames. Each frame reads the history from one
+// texture and writes the new average into the other, then the two swap. The
+// fragment path writes it as a color target, the compute path as a storage
+// texture.
+pub struct Accumulation {
+    textures: [Texture; 2],
+    views: [TextureView; 2],
+    uniform_bind_group_layout: BindGroupLayout,
+    uniform_bind_groups: Option<[BindGroup; 2]>,
+    compute_bind_group_layout: BindGroupLayout,
+    compute_bind_groups: Option<[BindGroup; 2]>,
+    binding: u32,
+
+    current: usize,
+}
+
+impl Accumulation {
+    pub const FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+    pub fn new(binding: u32, app: &App) -> Self {
+        let textures = [0, 1].map(|_| Self::create_texture(app));
+        let views = [0, 1].map(|i| textures[i].create_view(&TextureViewDescriptor::default()));
+
+        let uniform_bind_group_layout = app.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding,
+                    visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                }
+            ],
+            label: Some("Accumulation bind group layout")
+        });
+
+        let compute_bind_group_layout = app.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: binding + 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                        view_dimension: TextureViewDimension::D2
+                    },
+                    count: None
+                }
+            ],
+            label: Some("Accumulation compute bind group layout")
+        });
+
+        Self {
+            textures,
+            views,
+            uniform_bind_group_layout,
+            uniform_bind_groups: None,
+            compute_bind_group_layout,
+            compute_bind_groups: None,
+            binding,
+
+            current: 0,
+        }
+    }
+
+    pub fn uniform_bind_group_layout(&self) -> &BindGroupLayout {
+        &self.uniform_bind_group_layout
+    }
+
+    // Bind group with the history read by this frame.
+    pub fn uniform_bind_group(&self) -> &BindGroup {
+        &self.uniform_bind_groups.as_ref().unwrap()[self.current]
+    }
+
+    // Layout used instead of `uniform_bind_group_layout` by the compute path,
+    // it also holds the target as a storage texture.
+    pub fn compute_bind_group_layout(&self) -> &BindGroupLayout {
+        &self.compute_bind_group_layout
+    }
+
+    // Texture the new average is written to.
+    pub fn target(&self) -> &TextureView {
+        &self.views[1 - self.current]
+    }
+
+    pub fn compute_bind_group(&self) -> &BindGroup {
+        &self.compute_bind_groups.as_ref().unwrap()[self.current]
+    }
+
+    // Same texture as `target`, bound like the history for reading it back
+    // in the same frame.
+    pub fn target_bind_group(&self) -> &BindGroup {
+        &self.uniform_bind_groups.as_ref().unwrap()[1 - self.current]
+    }
+
+    pub fn init(&mut self, binding: u32, app: &App) {
+        self.binding = binding;
+
+        let uniform_bind_groups = [0, 1].map(|i| app.device.create_bind_group(&BindGroupDescriptor {
+            layout: &self.uniform_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding,
+                    resource: BindingResource::TextureView(&self.views[i])
+                }
+            ],
+            label: Some("Accumulation bind group")
+        }));
+
+        let compute_bind_groups = [0, 1].map(|i| app.device.create_bind_group(&BindGroupDescriptor {
+            layout: &self.compute_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding,
+                    resource: BindingResource::TextureView(&self.views[i])
+                },
+                BindGroupEntry {
+                    binding: binding + 1,
+                    resource: BindingResource::TextureView(&self.views[1 - i])
+                }
+            ],
+            label: Some("Accumulation compute bind group")
+        }));
+
+        self.uniform_bind_groups = Some(uniform_bind_groups);
+        self.compute_bind_groups = Some(compute_bind_groups);
+    }
+
+    pub fn resize(&mut self, app: &App) {
+        self.textures = [0, 1].map(|_| Self::create_texture(app));
+        self.views = [0, 1].map(|i| self.textures[i].create_view(&TextureViewDescriptor::default()));
+
+        if self.uniform_bind_groups.is_some() {
+            self.init(self.binding, app);
+        }
+    }
+
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+    }
+
+    fn create_texture(app: &App) -> Texture {
+        app.device.create_texture(&TextureDescriptor {
+            label: Some("Accumulation texture"),
+            size: Extent3d {
+                width: app.size.width.max(1),
+                height: app.size.height.max(1),
+                depth_or_array_layers: 1
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING,
+            view_formats: &[]
+        })
+    }
+}
@@ -1,6 +1,3 @@
-use bytemuck::Zeroable;
-use bytemuck::Pod;
-use bytemuck::cast_slice;
 use util::BufferInitDescriptor;
 use util::DeviceExt;
 use winit::event::ElementState;
@@ -11,14 +8,7 @@ use cgmath::*;
 use log::*;
 
 use crate::App;
-
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-#[derive(Zeroable, Pod)]
-pub struct CameraUniform {
-    pub(crate) rot: [[f32; 3]; 3],
-    pub(crate) pos: [f32; 3],
-}
+pub use crate::uniforms::CameraUniform;
 
 #[derive(Debug)]
 pub struct Camera {
@@ -40,22 +30,7 @@ impl Camera {
 
         let uniform_buffer = app.device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera uniform (buffer)"),
-            // contents: bytemuck::cast_slice(&[uniform]),
-            contents: {
-                let range = (0..16).into_iter();
-                let mut out = Vec::<u8>::new();
-
-                for i in range {
-                    let bytes = bytemuck::cast_slice::<f32, u8>(&[i as f32]).to_owned();
-
-                    out.push(bytes[0]);
-                    out.push(bytes[1]);
-                    out.push(bytes[2]);
-                    out.push(bytes[3]);
-                }
-
-                out
-            }.as_slice(),
+            contents: &uniform.to_wgsl_bytes(),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
         });
 
@@ -63,7 +38,7 @@ impl Camera {
             entries: &[
                 BindGroupLayoutEntry {
                     binding,
-                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -114,43 +89,19 @@ impl Camera {
     pub fn update_uniforms(&mut self, app: &App) {
         self.uniform = self.build();
 
-        app.queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            {
-                let mut out = Vec::<u8>::new();
-
-                trace!("rotate: {:#?}", self.uniform.rot);
-
-                out.append(&mut cast_slice(&[self.uniform.rot[0][0]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.rot[1][0]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.rot[2][0]]).to_owned());
-
-                out.append(&mut vec![0, 0, 0, 0]);
+        trace!("rotate: {:#?}", self.uniform.rot);
+        trace!("{:?}", self.uniform.pos);
 
-                out.append(&mut cast_slice(&[self.uniform.rot[0][1]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.rot[1][1]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.rot[2][1]]).to_owned());
-
-                out.append(&mut vec![0, 0, 0, 0]);
-
-                out.append(&mut cast_slice(&[self.uniform.rot[0][2]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.rot[1][2]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.rot[2][2]]).to_owned());
-
-                out.append(&mut vec![0, 0, 0, 0]);
-
-                out.append(&mut cast_slice(&[self.uniform.pos[0]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.pos[1]]).to_owned());
-                out.append(&mut cast_slice(&[self.uniform.pos[2]]).to_owned());
-
-                out.append(&mut vec![0, 0, 0, 0]);
+        app.queue.write_buffer(&self.uniform_buffer, 0, &self.uniform.to_wgsl_bytes());
+    }
 
-                trace!("{:?}", self.uniform.pos);
+    // Origin and direction of the ray `render_pixel` casts through `uv`, taken
+    // from the last uploaded uniform. `uv` is the clip position with x scaled
+    // by the aspect ratio.
+    pub fn ray(&self, uv: [f32; 2]) -> ([f32; 3], [f32; 3]) {
+        let dir = Matrix3::from(self.uniform.rot) * Vector3::new(1.0, uv[0], uv[1]).normalize();
 
-                out
-            }.as_slice()
-        );
+        (self.uniform.pos, dir.into())
     }
 
     fn build(&self) -> CameraUniform {
@@ -0,0 +1,44 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+// 8 bit RGBA pixels, rows top to bottom.
+pub struct RenderImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl RenderImage {
+    // Picks the format from the extension, PPM for ".ppm" and PNG otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("ppm") => self.save_ppm(path),
+            _ => self.save_png(path),
+        }
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder.write_header()?.write_image_data(&self.data)?;
+
+        Ok(())
+    }
+
+    pub fn save_ppm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
+
+        for pixel in self.data.chunks_exact(4) {
+            file.write_all(&pixel[..3])?;
+        }
+
+        file.flush()
+    }
+}
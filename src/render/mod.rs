@@ -1,6 +1,13 @@
+pub mod accumulation;
 pub mod camera;
+pub mod image;
+pub mod shader;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watcher;
 
-use std::mem;
+use std::{collections::{BTreeMap, HashMap}, mem};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{panic::{self, AssertUnwindSafe}, path::PathBuf, time::Duration};
 
 use log::*;
 use wgpu::*;
@@ -12,8 +19,15 @@ use wgpu::util::DeviceExt;
 use winit::{dpi::PhysicalSize, event::*};
 use bytemuck::{Pod, Zeroable};
 
-use crate::{voxel::VoxelTree, App};
+use crate::{voxel::{MaterialUniform, VoxelSpace, VoxelTree}, App};
+use crate::uniforms::{MetaDataUniformRaw, RenderSettingsRaw};
+use accumulation::Accumulation;
 use camera::*;
+use image::RenderImage;
+pub use shader::{ShaderBuilder, ShaderError};
+use shader::SourceMap;
+#[cfg(not(target_arch = "wasm32"))]
+use watcher::FileWatcher;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -48,30 +62,28 @@ const VERTICES: &[Vertex] = &[
     Vertex { position: [1.0, -1.0, 0.0] },
 ];
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-#[derive(Zeroable, Pod)]
-struct MetaDataUniformRaw {
-    res: [f32; 2],
-    time: u32,
-
-    _offset: [u32; 4]
-}
-
+// The render settings share the bind group at `binding + 1`.
 struct MetaDataUniform {
     uniform: MetaDataUniformRaw,
     uniform_buffer: Buffer,
+    settings_buffer: Buffer,
     uniform_bind_group_layout: BindGroupLayout,
     uniform_bind_group: Option<BindGroup>
 }
 
 impl MetaDataUniform {
-    pub fn new(raw: MetaDataUniformRaw, binding: u32, app: &App) -> Self {
+    pub fn new(raw: MetaDataUniformRaw, settings: RenderSettingsRaw, binding: u32, app: &App) -> Self {
         let uniform = raw;
 
         let uniform_buffer = app.device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Meta data uniform (buffer)"),
-            contents: bytemuck::cast_slice(&[uniform]),
+            contents: &uniform.to_wgsl_bytes(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+        });
+
+        let settings_buffer = app.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Render settings uniform (buffer)"),
+            contents: &settings.to_wgsl_bytes(),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
         });
 
@@ -79,7 +91,17 @@ impl MetaDataUniform {
             entries: &[
                 BindGroupLayoutEntry {
                     binding,
-                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: binding + 1,
+                    visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -95,6 +117,7 @@ impl MetaDataUniform {
             uniform,
             uniform_bind_group_layout,
             uniform_buffer,
+            settings_buffer,
             uniform_bind_group: None,
         }
     }
@@ -114,6 +137,10 @@ impl MetaDataUniform {
                 BindGroupEntry {
                     binding,
                     resource: self.uniform_buffer.as_entire_binding()
+                },
+                BindGroupEntry {
+                    binding: binding + 1,
+                    resource: self.settings_buffer.as_entire_binding()
                 }
             ],
             label: Some("Meta data bind group")
@@ -129,47 +156,163 @@ impl MetaDataUniform {
     pub fn update(&mut self, uniform: MetaDataUniformRaw, app: &App) {
         self.uniform = uniform;
 
-        app.queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[self.uniform])
-        )
+        app.queue.write_buffer(&self.uniform_buffer, 0, &self.uniform.to_wgsl_bytes())
+    }
+
+    pub fn update_settings(&mut self, settings: RenderSettingsRaw, app: &App) {
+        app.queue.write_buffer(&self.settings_buffer, 0, &settings.to_wgsl_bytes())
     }
 }
 
 pub struct ShaderCreateDescriptor {
-    pub shdaer_source: String
+    pub shdaer_source: String,
+    // Set by `ShaderBuilder` so errors point into the original files.
+    pub source_map: Option<SourceMap>
 }
 
 pub struct Shader {
-    shader_module: ShaderModule
+    shader_module: ShaderModule,
+    // Defines the source was preprocessed with, only known for shaders built
+    // by `ShaderBuilder`. `Render` caches pipelines by them.
+    defines: BTreeMap<String, String>,
+    builder: Option<ShaderBuilder>
 }
 
 impl Shader {
-    pub fn new(desc: &ShaderCreateDescriptor, app: &App) -> Self {
+    pub fn new(desc: &ShaderCreateDescriptor, app: &App) -> Result<Self, ShaderError> {
+        let source_map = desc.source_map.as_ref();
+
+        shader::validate(&desc.shdaer_source, source_map)?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        app.device.push_error_scope(ErrorFilter::Validation);
+
         let shader_module = app.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Shader"),
             source: ShaderSource::Wgsl(desc.shdaer_source.as_str().into())
         });
 
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(e) = pollster::block_on(app.device.pop_error_scope()) {
+            return Err(ShaderError::Module(shader::rewrite_locations(&e.to_string(), source_map)));
+        }
+
+        Ok(Self {
+            shader_module,
+            defines: BTreeMap::new(),
+            builder: None
+        })
+    }
+
+    pub fn defines(&self) -> &BTreeMap<String, String> {
+        &self.defines
+    }
+}
+
+// Fragment traces every pixel in `fs_main` on a fullscreen quad. Compute runs
+// `cs_main` over 8x8 tiles into the accumulation texture, then `fs_blit` copies
+// it to the surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    #[default]
+    Fragment,
+    Compute
+}
+
+// Values are `TRACE_MODE_*` in the shaders. Ray casting shows the material of
+// the first hit, path tracing follows the bounces with `trace_ray`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceMode {
+    #[default]
+    PathTracing = 0,
+    RayCasting = 1
+}
+
+// Quality knobs read by the shaders every frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub samples: u32,
+    pub max_bounces: u32,
+    pub mode: TraceMode,
+    pub exposure: f32,
+}
+
+impl RenderSettings {
+    fn raw(&self) -> RenderSettingsRaw {
+        RenderSettingsRaw {
+            samples: self.samples,
+            max_bounces: self.max_bounces,
+            mode: self.mode as u32,
+            exposure: self.exposure
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
         Self {
-            shader_module
+            samples: 10,
+            max_bounces: 4,
+            mode: TraceMode::PathTracing,
+            exposure: 1.0,
         }
     }
 }
 
+// Safety limits for `RenderSettings`, a single frame above them can run into
+// the driver timeout.
+const MAX_SAMPLES: u32 = 4096;
+const MAX_BOUNCES: u32 = 256;
+
+// Must match `@workgroup_size` of `cs_main`.
+const TILE_SIZE: u32 = 8;
+
 pub struct RenderCreateDescriptor {
     pub shader: Shader,
-    pub camera: (Point3<f32>, Vector3<f32>)
+    pub camera: (Point3<f32>, Vector3<f32>),
+    pub mode: RenderMode
+}
+
+// Voxel under a screen position. `node` indexes the nodes of the tree used by
+// `instance`, `position` and `normal` are in world space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pick {
+    pub instance: usize,
+    pub node: usize,
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub material: MaterialUniform,
+}
+
+struct Pipelines {
+    render: RenderPipeline,
+    compute: Option<ComputePipeline>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct HotReload {
+    main_dir: PathBuf,
+    std_dir: PathBuf,
+    watcher: FileWatcher,
 }
 
 pub struct Render {
-    render_pipeline: RenderPipeline,
+    mode: RenderMode,
+    // Pipelines of every define set used so far, `defines` selects the
+    // current one. `shader_builder` compiles the missing ones.
+    pipelines: HashMap<BTreeMap<String, String>, Pipelines>,
+    defines: BTreeMap<String, String>,
+    shader_builder: Option<ShaderBuilder>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    hot_reload: Option<HotReload>,
 
     vertex_buffer: Buffer,
 
     meta_data: MetaDataUniform,
-    voxel_tree: VoxelTree,
+    settings: RenderSettings,
+    voxel_space: VoxelSpace,
+    accumulation: Accumulation,
 
     camera: Camera,
     camera_controller: CameraController,
@@ -177,69 +320,35 @@ pub struct Render {
 
 impl Render {
     pub fn new(desc: RenderCreateDescriptor, app: &App) -> Self {
-        let shader = desc.shader.shader_module;
+        let shader = desc.shader;
 
         let mut camera = Camera::new(desc.camera.0, desc.camera.1, 0, app);
 
         let mut meta_data = MetaDataUniform::new(MetaDataUniformRaw {
             res: [app.size.width as f32, app.size.height as f32],
             time: 0,
-            _offset: [0; 4]
-        }, 0, app);
-
-        let mut voxel_tree = VoxelTree::new(app, 0, 9);
-        voxel_tree.load("scene_vox.json".to_string());
-       
-        let render_pipeline_layout = app.device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("Render pipeline layout"),
-            bind_group_layouts: &[
-                camera.uniform_bind_group_layout(),
-                meta_data.uniform_bind_group_layout(),
-                voxel_tree.uniform_bind_group_layout(),
-            ],
-            push_constant_ranges: &[]
-        });
+            frame: 0
+        }, RenderSettings::default().raw(), 0, app);
+
+        let mut voxel_tree = VoxelTree::new();
+        if let Err(e) = voxel_tree.load("scene_vox.json".to_string()) {
+            error!("Error to load scene file: {}", e);
+        }
+
+        let mut voxel_space = VoxelSpace::new(0, app);
+        voxel_space.add_tree(voxel_tree);
+
+        let mut accumulation = Accumulation::new(0, app);
 
         camera.init(0, app);
         meta_data.init(0, app);
-        voxel_tree.init(0, app);
-
-        let render_pipeline = app.device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[
-                    Vertex::desc()
-                ]
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(ColorTargetState {
-                    format: app.surface_config.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL
-                })]
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None
-        });
+        accumulation.init(0, app);
+
+        if let Err(e) = voxel_space.init(0, app) {
+            error!("Error to upload scene: {}", e);
+        }
+
+        let pipelines = create_pipelines(&shader.shader_module, desc.mode, &camera, &meta_data, &voxel_space, &accumulation, app);
 
         let vertex_buffer = app.device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Vertex buffer"),
@@ -250,78 +359,407 @@ impl Render {
         let camera_controller = CameraController::new(15.0);
 
         Self {
-            render_pipeline,
+            mode: desc.mode,
+            pipelines: HashMap::from([(shader.defines.clone(), pipelines)]),
+            defines: shader.defines,
+            shader_builder: shader.builder,
+            #[cfg(not(target_arch = "wasm32"))]
+            hot_reload: None,
             vertex_buffer,
             meta_data,
+            settings: RenderSettings::default(),
             camera,
             camera_controller,
-            voxel_tree
+            voxel_space,
+            accumulation
         }
     }
 
-    fn render(&self, app: &App) -> Result<(), SurfaceError> {
-        let output = app.surface.get_current_texture()?;
-        let view = output.texture.create_view(&TextureViewDescriptor::default());
+    pub fn voxel_space(&self) -> &VoxelSpace {
+        &self.voxel_space
+    }
 
-        let mut encoder = app.device.create_command_encoder(&CommandEncoderDescriptor {
-            label: Some("Command encoder")
+    pub fn voxel_space_mut(&mut self) -> &mut VoxelSpace {
+        &mut self.voxel_space
+    }
+
+    // Casts the ray of the pixel at `x`, `y` in window pixels from the top left
+    // corner on the CPU, with the same math as `render_pixel`.
+    pub fn pick(&self, x: f32, y: f32) -> Option<Pick> {
+        let [width, height] = self.meta_data.uniform.res;
+
+        let ndc = [x / width * 2.0 - 1.0, 1.0 - y / height * 2.0];
+        let uv = [ndc[0] * width / height, ndc[1]];
+
+        let (origin, dir) = self.camera.ray(uv);
+        let (instance, hit) = self.voxel_space.raycast(origin, dir)?;
+
+        Some(Pick {
+            instance,
+            node: hit.node,
+            position: [0, 1, 2].map(|axis| origin[axis] + dir[axis] * hit.fraction),
+            normal: hit.normal,
+            material: hit.material,
+        })
+    }
+
+    // Rebuilds the pipelines whenever a file under `main_dir` or `std_dir`
+    // changes, `main_dir` has to contain main.wgsl. A broken shader is logged
+    // with its original file and line and the last working pipelines stay.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_shader(&mut self, main_dir: impl Into<PathBuf>, std_dir: impl Into<PathBuf>) {
+        let main_dir = main_dir.into();
+        let std_dir = std_dir.into();
+
+        let watcher = FileWatcher::new(vec![main_dir.clone(), std_dir.clone()], Duration::from_millis(250));
+
+        self.hot_reload = Some(HotReload {
+            main_dir,
+            std_dir,
+            watcher
         });
+    }
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0
-                        }),
-                        store: StoreOp::Store
-                    }
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None
-            });
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shader(&mut self, app: &App) {
+        let Some(hot_reload) = &self.hot_reload else {
+            return;
+        };
+
+        let builder = ShaderBuilder::new(String::new())
+            .library_path("main", &hot_reload.main_dir)
+            .and_then(|builder| builder.library_path("std", &hot_reload.std_dir));
+
+        let builder = match builder {
+            Ok(builder) => builder.with_defines(self.defines.clone()),
+            Err(e) => {
+                error!("Error to read shader sources: {}", e);
+                return;
+            }
+        };
+
+        // The preprocessor panics on includes it can't find.
+        let Ok((source, map)) = panic::catch_unwind(AssertUnwindSafe(|| builder.preprocess_with_map())) else {
+            error!("Error to preprocess shader, keeping the last one");
+            return;
+        };
+
+        let desc = ShaderCreateDescriptor {
+            shdaer_source: source,
+            source_map: Some(map)
+        };
+
+        let pipelines = Shader::new(&desc, app).and_then(|shader| self.try_create_pipelines(&shader, app));
+
+        let pipelines = match pipelines {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                error!("Shader error: {}", e);
+                return;
+            }
+        };
+
+        // Pipelines of the other define sets still use the old sources.
+        self.pipelines.clear();
+        self.pipelines.insert(self.defines.clone(), pipelines);
+        self.shader_builder = Some(builder);
+        self.reset_accumulation();
+
+        info!("Shader reloaded");
+    }
+
+    pub fn defines(&self) -> &BTreeMap<String, String> {
+        &self.defines
+    }
+
+    pub fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    // Takes effect on the next frame without touching the pipelines. Changing
+    // only the exposure keeps the accumulated samples.
+    pub fn set_settings(&mut self, mut settings: RenderSettings, app: &App) {
+        if settings.samples > MAX_SAMPLES {
+            warn!("{} samples per frame is above the limit, using {}", settings.samples, MAX_SAMPLES);
+            settings.samples = MAX_SAMPLES;
+        }
+
+        if settings.max_bounces > MAX_BOUNCES {
+            warn!("{} bounces is above the limit, using {}", settings.max_bounces, MAX_BOUNCES);
+            settings.max_bounces = MAX_BOUNCES;
+        }
+
+        if (RenderSettings { exposure: self.settings.exposure, ..settings }) != self.settings {
+            self.reset_accumulation();
+        }
 
-            render_pass.set_pipeline(&self.render_pipeline);
+        self.settings = settings;
+        self.meta_data.update_settings(settings.raw(), app);
+    }
+
+    // Switches to the pipelines built with `defines`, compiling them on first
+    // use. On error the current pipelines are kept.
+    pub fn set_defines(&mut self, defines: BTreeMap<String, String>, app: &App) -> Result<(), ShaderError> {
+        if defines == self.defines {
+            return Ok(());
+        }
+
+        if !self.pipelines.contains_key(&defines) {
+            let builder = self.shader_builder.as_ref().ok_or(ShaderError::NoBuilder)?;
+            let shader = builder.clone().with_defines(defines.clone()).build(app)?;
+            let pipelines = self.try_create_pipelines(&shader, app)?;
+
+            self.pipelines.insert(defines.clone(), pipelines);
+        }
 
-            render_pass.set_bind_group(0, &self.camera.uniform_bind_group(), &[]);
-            render_pass.set_bind_group(1, &self.meta_data.uniform_bind_group(), &[]);
-            render_pass.set_bind_group(2, &self.voxel_tree.uniform_bind_group(), &[]);
+        self.defines = defines;
+        self.reset_accumulation();
 
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..6, 0..1);
+        Ok(())
+    }
+
+    fn try_create_pipelines(&self, shader: &Shader, app: &App) -> Result<Pipelines, ShaderError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        app.device.push_error_scope(ErrorFilter::Validation);
+
+        let pipelines = create_pipelines(&shader.shader_module, self.mode, &self.camera, &self.meta_data, &self.voxel_space, &self.accumulation, app);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(e) = pollster::block_on(app.device.pop_error_scope()) {
+            return Err(ShaderError::Pipeline(e.to_string()));
         }
 
+        Ok(pipelines)
+    }
+
+    fn current_pipelines(&self) -> &Pipelines {
+        &self.pipelines[&self.defines]
+    }
+
+    // Starts averaging the traced samples from scratch, the camera, the scene
+    // and the window size already call it when they change.
+    pub fn reset_accumulation(&mut self) {
+        self.meta_data.uniform.frame = 0;
+    }
+
+    fn render(&mut self, app: &App) -> Result<(), SurfaceError> {
+        let surface = app.surface.as_ref().expect("Headless app has no surface to render!");
+        let output = surface.get_current_texture()?;
+        let view = output.texture.create_view(&TextureViewDescriptor::default());
+
+        let mut encoder = app.device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Command encoder")
+        });
+
+        self.draw(&view, &mut encoder);
+
         app.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
+        self.next_frame();
+
         Ok(())
     }
 
+    // Draws one frame into an offscreen texture and reads it back, works with
+    // or without a window.
+    pub fn render_image(&mut self, app: &App) -> Result<RenderImage, BufferAsyncError> {
+        self.update(app);
+
+        let size = Extent3d {
+            width: app.size.width,
+            height: app.size.height,
+            depth_or_array_layers: 1
+        };
+
+        let texture = app.device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: app.surface_config.format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[]
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let bytes_per_row = (size.width * 4).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let output_buffer = app.device.create_buffer(&BufferDescriptor {
+            label: Some("Offscreen output (buffer)"),
+            size: bytes_per_row as u64 * size.height as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+
+        let mut encoder = app.device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Offscreen command encoder")
+        });
+
+        self.draw(&view, &mut encoder);
+
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All
+            },
+            ImageCopyBuffer {
+                buffer: &output_buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(size.height)
+                }
+            },
+            size
+        );
+
+        app.queue.submit(std::iter::once(encoder.finish()));
+
+        self.next_frame();
+
+        let slice = output_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        app.device.poll(Maintain::Wait);
+
+        receiver.recv().expect("Buffer mapping callback was dropped!")?;
+
+        let mut data = Vec::with_capacity((size.width * size.height * 4) as usize);
+
+        for row in slice.get_mapped_range().chunks_exact(bytes_per_row as usize) {
+            data.extend_from_slice(&row[..(size.width * 4) as usize]);
+        }
+
+        output_buffer.unmap();
+
+        // Window surfaces are usually BGRA, images are always RGBA.
+        if let TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb = app.surface_config.format {
+            for pixel in data.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        Ok(RenderImage {
+            width: size.width,
+            height: size.height,
+            data
+        })
+    }
+
+    fn draw(&self, view: &TextureView, encoder: &mut CommandEncoder) {
+        let clear = Operations {
+            load: LoadOp::Clear(Color::BLACK),
+            store: StoreOp::Store
+        };
+
+        let mut color_attachments = vec![Some(RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: clear
+        })];
+
+        let pipelines = self.current_pipelines();
+
+        if let Some(compute_pipeline) = &pipelines.compute {
+            self.dispatch(compute_pipeline, encoder);
+        } else {
+            color_attachments.push(Some(RenderPassColorAttachment {
+                view: self.accumulation.target(),
+                resolve_target: None,
+                ops: clear
+            }));
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Render pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None
+        });
+
+        render_pass.set_pipeline(&pipelines.render);
+
+        render_pass.set_bind_group(0, self.camera.uniform_bind_group(), &[]);
+        render_pass.set_bind_group(1, self.meta_data.uniform_bind_group(), &[]);
+        render_pass.set_bind_group(2, self.voxel_space.uniform_bind_group(), &[]);
+
+        // The blit shows the average the compute pass has just written.
+        if pipelines.compute.is_some() {
+            render_pass.set_bind_group(3, self.accumulation.target_bind_group(), &[]);
+        } else {
+            render_pass.set_bind_group(3, self.accumulation.uniform_bind_group(), &[]);
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..6, 0..1);
+    }
+
+    fn dispatch(&self, compute_pipeline: &ComputePipeline, encoder: &mut CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Compute pass"),
+            timestamp_writes: None
+        });
+
+        compute_pass.set_pipeline(compute_pipeline);
+
+        compute_pass.set_bind_group(0, self.camera.uniform_bind_group(), &[]);
+        compute_pass.set_bind_group(1, self.meta_data.uniform_bind_group(), &[]);
+        compute_pass.set_bind_group(2, self.voxel_space.uniform_bind_group(), &[]);
+        compute_pass.set_bind_group(3, self.accumulation.compute_bind_group(), &[]);
+
+        let [width, height] = self.meta_data.uniform.res.map(|x| x as u32);
+
+        compute_pass.dispatch_workgroups(width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE), 1);
+    }
+
     fn resize(&mut self, physical_size: PhysicalSize<u32>, app: &mut App) {
         app.size = physical_size;
         app.surface_config.width = physical_size.width;
         app.surface_config.height = physical_size.height;
-        app.surface.configure(&app.device, &app.surface_config);
+
+        if let Some(surface) = &app.surface {
+            surface.configure(&app.device, &app.surface_config);
+        }
+
+        self.accumulation.resize(app);
 
         self.meta_data.update(MetaDataUniformRaw {
             res: [app.size.width as f32, app.size.height as f32],
             time: self.meta_data.uniform().time,
-            _offset: [0; 4]
+            frame: 0
         }, app);
     }
 
+    fn next_frame(&mut self) {
+        self.accumulation.swap();
+        self.meta_data.uniform.frame += 1;
+    }
+
     fn update(&mut self, app: &App) {
-        self.meta_data.uniform.time += 1;
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.hot_reload.as_ref().is_some_and(|hot_reload| hot_reload.watcher.changed()) {
+            self.reload_shader(app);
+        }
 
-        if self.meta_data.uniform.time == 1000 {
-            self.meta_data.uniform.time = 0;
+        // Seeds the samples, it must not repeat while frames are accumulated.
+        self.meta_data.uniform.time = self.meta_data.uniform.time.wrapping_add(1);
+
+        if self.voxel_space.is_dirty() {
+            if let Err(e) = self.voxel_space.update_buffers(app) {
+                error!("Error to upload scene: {}", e);
+            }
+
+            self.reset_accumulation();
         }
 
         self.meta_data.update(self.meta_data.uniform, app);
@@ -331,6 +769,7 @@ impl Render {
         if self.camera_controller.handle_events(event) {
             self.camera_controller.handle_camera(&mut self.camera, &app);
             self.camera.update_uniforms(&app);
+            self.reset_accumulation();
         }
 
         match event {
@@ -350,4 +789,103 @@ impl Render {
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+fn create_pipelines(
+    shader: &ShaderModule,
+    mode: RenderMode,
+    camera: &Camera,
+    meta_data: &MetaDataUniform,
+    voxel_space: &VoxelSpace,
+    accumulation: &Accumulation,
+    app: &App
+) -> Pipelines {
+    let render_pipeline_layout = app.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Render pipeline layout"),
+        bind_group_layouts: &[
+            camera.uniform_bind_group_layout(),
+            meta_data.uniform_bind_group_layout(),
+            voxel_space.uniform_bind_group_layout(),
+            accumulation.uniform_bind_group_layout(),
+        ],
+        push_constant_ranges: &[]
+    });
+
+    let compute_pipeline = match mode {
+        RenderMode::Fragment => None,
+        RenderMode::Compute => {
+            let compute_pipeline_layout = app.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Compute pipeline layout"),
+                bind_group_layouts: &[
+                    camera.uniform_bind_group_layout(),
+                    meta_data.uniform_bind_group_layout(),
+                    voxel_space.uniform_bind_group_layout(),
+                    accumulation.compute_bind_group_layout(),
+                ],
+                push_constant_ranges: &[]
+            });
+
+            Some(app.device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("Compute pipeline"),
+                layout: Some(&compute_pipeline_layout),
+                module: shader,
+                entry_point: "cs_main"
+            }))
+        }
+    };
+
+    let color_target = ColorTargetState {
+        format: app.surface_config.format,
+        blend: Some(BlendState::REPLACE),
+        write_mask: ColorWrites::ALL
+    };
+
+    let accumulation_target = ColorTargetState {
+        format: Accumulation::FORMAT,
+        blend: None,
+        write_mask: ColorWrites::ALL
+    };
+
+    let (fragment_entry_point, targets) = match mode {
+        RenderMode::Fragment => ("fs_main", vec![Some(color_target), Some(accumulation_target)]),
+        RenderMode::Compute => ("fs_blit", vec![Some(color_target)]),
+    };
+
+    let render_pipeline = app.device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Render pipeline"),
+        layout: Some(&render_pipeline_layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[
+                Vertex::desc()
+            ]
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &targets
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: Some(Face::Back),
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None
+    });
+
+    Pipelines {
+        render: render_pipeline,
+        compute: compute_pipeline
+    }
+}
@@ -0,0 +1,369 @@
+use std::{collections::{BTreeMap, HashMap}, error, fmt};
+
+use gimura_preprocessor_lib::prelude::*;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::App;
+use super::{Shader, ShaderCreateDescriptor};
+
+// Every file of shader_std as `(path relative to shader_std, source)`.
+const SHADER_STD: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/shader_std.rs"));
+const GENERATED_UNIFORMS: &str = include_str!(concat!(env!("OUT_DIR"), "/uniforms.wgsl"));
+
+// Appended to every source line before preprocessing as `//#src <file> <line>`
+// and stripped again afterwards, the preprocessor keeps it with its line.
+const LINE_MARKER: &str = "//#src";
+
+static NAGA_LOCATION: Lazy<Regex> = Lazy::new(|| Regex::new(r"wgsl:(\d+):\d+").unwrap());
+
+#[derive(Clone)]
+struct SourceFile {
+    path: String,
+    source: String,
+}
+
+// Original file and line of every line of a preprocessed shader.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<String>,
+    lines: Vec<Option<(usize, usize)>>,
+}
+
+impl SourceMap {
+    // Takes a 1-based line of the preprocessed shader and returns the path and
+    // 1-based line it came from.
+    pub fn locate(&self, line: usize) -> Option<(&str, usize)> {
+        let (file, line) = (*self.lines.get(line.checked_sub(1)?)?)?;
+
+        Some((self.files[file].as_str(), line))
+    }
+}
+
+// Preprocesses a main shader at runtime. The bundled shader_std is available as
+// the "std" library and the structs generated from the Rust uniforms as
+// "generated", so a main source only needs `//! include "std" "std.wgsl"`.
+//
+// Libraries are looked up by file name like `CodeSource::from_path` does, the
+// main source itself is "main.wgsl" in the "main" library.
+//
+// Defines are set before the first line is preprocessed, files guard their
+// defaults with `//! ifndef` so these win.
+#[derive(Clone)]
+pub struct ShaderBuilder {
+    libraries: HashMap<String, HashMap<String, SourceFile>>,
+    defines: BTreeMap<String, String>,
+}
+
+impl ShaderBuilder {
+    pub fn new(main: impl Into<String>) -> Self {
+        let std = SHADER_STD.iter()
+            .map(|(path, source)| (file_name(path).to_string(), SourceFile {
+                path: format!("shader_std/{}", path),
+                source: source.to_string(),
+            }))
+            .collect();
+
+        let mut libraries = HashMap::new();
+
+        libraries.insert("std".to_string(), std);
+        libraries.insert("generated".to_string(), HashMap::from([
+            ("uniforms.wgsl".to_string(), SourceFile {
+                path: "generated/uniforms.wgsl".to_string(),
+                source: GENERATED_UNIFORMS.to_string(),
+            })
+        ]));
+        libraries.insert("main".to_string(), HashMap::from([
+            ("main.wgsl".to_string(), SourceFile {
+                path: "main.wgsl".to_string(),
+                source: main.into(),
+            })
+        ]));
+
+        Self {
+            libraries,
+            defines: BTreeMap::new()
+        }
+    }
+
+    pub fn define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    // Replaces every define set so far.
+    pub fn with_defines(mut self, defines: BTreeMap<String, String>) -> Self {
+        self.defines = defines;
+        self
+    }
+
+    pub fn defines(&self) -> &BTreeMap<String, String> {
+        &self.defines
+    }
+
+    // Adds files to a library, replacing files with the same name. Adding to
+    // "std" overrides single files of the bundled library.
+    pub fn library(self, name: impl Into<String>, sources: HashMap<String, String>) -> Self {
+        let name = name.into();
+
+        let files = sources.into_iter()
+            .map(|(file, source)| {
+                let path = format!("{}/{}", name, file);
+
+                (file, SourceFile { path, source })
+            })
+            .collect();
+
+        self.add_files(name, files)
+    }
+
+    // Adds every file under `path` to a library.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn library_path(self, name: impl Into<String>, path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let mut files = HashMap::new();
+
+        read_sources(path.as_ref(), &mut files)?;
+
+        Ok(self.add_files(name.into(), files))
+    }
+
+    fn add_files(mut self, name: String, files: HashMap<String, SourceFile>) -> Self {
+        self.libraries.entry(name)
+            .or_default()
+            .extend(files);
+
+        self
+    }
+
+    pub fn preprocess(&self) -> String {
+        self.preprocess_with_map().0
+    }
+
+    pub fn preprocess_with_map(&self) -> (String, SourceMap) {
+        let mut preprocessor = Preporcessor::new(PreprocessorOptions {
+            defines: self.defines.clone().into_iter().collect(),
+            ..Default::default()
+        });
+        let mut map = SourceMap::default();
+
+        for (name, files) in &self.libraries {
+            let sources = files.iter()
+                .map(|(file, source)| {
+                    map.files.push(source.path.clone());
+
+                    (file.clone(), mark_lines(&source.source, map.files.len() - 1))
+                })
+                .collect();
+
+            preprocessor.add_source(name.clone(), CodeSource::new(sources));
+        }
+
+        let marked = preprocessor.preprocess("main".to_string(), "main.wgsl".to_string());
+        let mut source = String::with_capacity(marked.len());
+
+        for line in marked.lines() {
+            let (code, origin) = match line.rfind(LINE_MARKER) {
+                Some(start) => (&line[..start], parse_marker(&line[start + LINE_MARKER.len()..])),
+                None => (line, None),
+            };
+
+            source.push_str(code.trim_end());
+            source.push('\n');
+            map.lines.push(origin);
+        }
+
+        (source, map)
+    }
+
+    pub fn build(&self, app: &App) -> Result<Shader, ShaderError> {
+        let (source, map) = self.preprocess_with_map();
+
+        let mut shader = Shader::new(&ShaderCreateDescriptor {
+            shdaer_source: source,
+            source_map: Some(map)
+        }, app)?;
+
+        shader.defines = self.defines.clone();
+        shader.builder = Some(self.clone());
+
+        Ok(shader)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderLocation {
+    pub path: String,
+    pub line: usize,
+}
+
+impl fmt::Display for ShaderLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.path, self.line)
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Parse { location: Option<ShaderLocation>, message: String },
+    Validation { location: Option<ShaderLocation>, message: String },
+    // Rejected by wgpu after naga accepted it, locations in the message are
+    // already rewritten.
+    Module(String),
+    Pipeline(String),
+    // Only shaders built by `ShaderBuilder` can be rebuilt with other defines.
+    NoBuilder,
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { location: Some(location), message } => write!(f, "{}: parse error: {}", location, message),
+            Self::Parse { location: None, message } => write!(f, "parse error: {}", message),
+            Self::Validation { location: Some(location), message } => write!(f, "{}: validation error: {}", location, message),
+            Self::Validation { location: None, message } => write!(f, "validation error: {}", message),
+            Self::Module(message) => write!(f, "failed to create shader module: {}", message),
+            Self::Pipeline(message) => write!(f, "failed to create pipelines: {}", message),
+            Self::NoBuilder => write!(f, "shader was not built by a ShaderBuilder, it can't be rebuilt with other defines"),
+        }
+    }
+}
+
+impl error::Error for ShaderError {}
+
+// Maps a 1-based line of the preprocessed shader back through `map`, lines
+// without a map are reported as `wgsl:<line>` like naga does.
+fn locate(map: Option<&SourceMap>, line: usize) -> ShaderLocation {
+    match map.and_then(|map| map.locate(line)) {
+        Some((path, line)) => ShaderLocation { path: path.to_string(), line },
+        None => ShaderLocation { path: "wgsl".to_string(), line },
+    }
+}
+
+// Parses and validates a preprocessed shader with naga before it reaches wgpu.
+pub(crate) fn validate(source: &str, map: Option<&SourceMap>) -> Result<(), ShaderError> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| ShaderError::Parse {
+            location: e.location(source).map(|location| locate(map, location.line_number as usize)),
+            message: e.message().to_string(),
+        })?;
+
+    Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|e| {
+            let mut message = e.as_inner().to_string();
+            let mut cause = error::Error::source(e.as_inner());
+
+            while let Some(e) = cause {
+                message += &format!(": {}", e);
+                cause = e.source();
+            }
+
+            ShaderError::Validation {
+                location: e.location(source).map(|location| locate(map, location.line_number as usize)),
+                message,
+            }
+        })?;
+
+    Ok(())
+}
+
+// Rewrites the `wgsl:<line>:<column>` locations naga prints in wgpu errors to
+// the original files.
+pub(crate) fn rewrite_locations(message: &str, map: Option<&SourceMap>) -> String {
+    NAGA_LOCATION.replace_all(message, |captures: &Captures| {
+        locate(map, captures[1].parse().unwrap_or(0)).to_string()
+    }).into_owned()
+}
+
+fn mark_lines(source: &str, file: usize) -> String {
+    source.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let trimmed = line.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with("//!") {
+                line.to_string()
+            } else {
+                format!("{} {} {} {}", line, LINE_MARKER, file, i + 1)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parse_marker(marker: &str) -> Option<(usize, usize)> {
+    let mut words = marker.split_whitespace();
+
+    Some((words.next()?.parse().ok()?, words.next()?.parse().ok()?))
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_sources(dir: &std::path::Path, files: &mut HashMap<String, SourceFile>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            read_sources(&path, files)?;
+        } else if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            files.insert(name.to_string(), SourceFile {
+                path: path.display().to_string(),
+                source: std::fs::read_to_string(&path)?,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn std_line(path: &str, line: usize) -> &'static str {
+        let (_, source) = SHADER_STD.iter()
+            .find(|(file, _)| format!("shader_std/{}", file) == path)
+            .expect("not a shader_std file");
+
+        source.lines().nth(line - 1).unwrap()
+    }
+
+    fn error_location(builder: ShaderBuilder) -> ShaderLocation {
+        let (source, map) = builder.preprocess_with_map();
+
+        match validate(&source, Some(&map)) {
+            Err(ShaderError::Parse { location: Some(location), .. } | ShaderError::Validation { location: Some(location), .. }) => location,
+            other => panic!("expected an error with a location, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn marked_lines_map_back_to_their_file() {
+        let marked = mark_lines("//! define A \"1\"\n\nlet a = 1;\nlet b = 2;", 3);
+        let lines = marked.lines()
+            .map(|line| line.rfind(LINE_MARKER).and_then(|start| parse_marker(&line[start + LINE_MARKER.len()..])))
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, [None, None, Some((3, 3)), Some((3, 4))]);
+    }
+
+    #[test]
+    fn error_in_std_file_maps_to_std_line() {
+        let location = error_location(ShaderBuilder::new("//! include \"std\" \"std.wgsl\"\n")
+            .define("FAR_DISTANCE", "undefined_far_distance"));
+
+        assert_eq!(location.path, "shader_std/render/ray_casting.wgsl");
+        assert!(std_line(&location.path, location.line).contains("FAR_DISTANCE"), "{} is not a FAR_DISTANCE line", location);
+    }
+
+    #[test]
+    fn error_in_main_maps_to_main_line() {
+        let location = error_location(ShaderBuilder::new("//! include \"std\" \"std.wgsl\"\n\nfn f() -> f32 {\n    return undefined_value;\n}\n"));
+
+        assert_eq!(location, ShaderLocation { path: "main.wgsl".to_string(), line: 4 });
+    }
+}
@@ -0,0 +1,110 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}, thread, time::{Duration, SystemTime}};
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver}, Arc};
+
+// Polls the modification times of every file under a set of paths from a
+// background thread. The thread stops within one interval after the watcher
+// is dropped.
+pub struct FileWatcher {
+    receiver: Receiver<()>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+    pub fn new(paths: Vec<PathBuf>, interval: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        thread::spawn(move || {
+            let mut last = snapshot(&paths);
+
+            loop {
+                thread::sleep(interval);
+
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let current = snapshot(&paths);
+
+                if current != last {
+                    last = current;
+
+                    if sender.send(()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            receiver,
+            stop
+        }
+    }
+
+    // True if anything changed since the last call.
+    pub fn changed(&self) -> bool {
+        self.receiver.try_iter().count() > 0
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn snapshot(paths: &[PathBuf]) -> HashMap<PathBuf, SystemTime> {
+    let mut files = HashMap::new();
+
+    for path in paths {
+        collect(path, &mut files);
+    }
+
+    files
+}
+
+fn collect(path: &Path, files: &mut HashMap<PathBuf, SystemTime>) {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            collect(&entry.path(), files);
+        }
+    } else if let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        files.insert(path.to_path_buf(), modified);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changes_and_stops_when_dropped() {
+        let dir = std::env::temp_dir().join(format!("fast-voxel-watcher-test-{}", std::process::id()));
+        let file = dir.join("main.wgsl");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, "a").unwrap();
+
+        let watcher = FileWatcher::new(vec![dir.clone()], Duration::from_millis(10));
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!watcher.changed());
+
+        fs::File::options().write(true).open(&file).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(watcher.changed());
+
+        let stop = watcher.stop.clone();
+
+        drop(watcher);
+        thread::sleep(Duration::from_millis(100));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(Arc::strong_count(&stop), 1);
+    }
+}
@@ -0,0 +1,151 @@
+// Every struct shared with the shaders. build.rs includes this file on its own
+// to generate their WGSL declarations, so it may only depend on bytemuck and
+// the layout derive.
+
+use bytemuck::{Zeroable, Pod};
+use wgsl_structure_converter::ToWgslShaderSlice;
+
+// Deepest tree `cast_ray_tree` can walk without dropping nodes, every level
+// leaves at most 7 siblings on its stack. Deeper trees are rejected on upload.
+pub const MAX_TREE_DEPTH: usize = 9;
+pub const TREE_STACK_SIZE: usize = 7 * MAX_TREE_DEPTH + 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(name = "MetaDataUniform", uniform)]
+pub(crate) struct MetaDataUniformRaw {
+    pub(crate) res: [f32; 2],
+    pub(crate) time: u32,
+    pub(crate) frame: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(name = "RenderSettings", uniform)]
+pub(crate) struct RenderSettingsRaw {
+    pub(crate) samples: u32,
+    pub(crate) max_bounces: u32,
+    pub(crate) mode: u32,
+    pub(crate) exposure: f32
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(uniform)]
+pub struct CameraUniform {
+    #[wgsl(name = "matrix", transpose)]
+    pub(crate) rot: [[f32; 3]; 3],
+    pub(crate) pos: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(name = "Material")]
+pub struct MaterialUniform {
+    pub emmitance: [f32; 3],
+    pub reflectance: [f32; 3],
+    pub roughness: f32,
+    pub opacity: f32
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            emmitance: [1.0, 1.0, 1.0],
+            reflectance: [1.0, 1.0, 1.0],
+            roughness: 0.0,
+            opacity: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(name = "Cube")]
+pub struct CompiledUniform {
+    pub position: [f32; 3],
+    #[wgsl(transpose)]
+    pub rotation: [[f32; 3]; 3],
+    pub size: f32,
+
+    pub material: MaterialUniform,
+    pub childs: [f32; 8],
+    pub is_leaf: f32,
+    pub is_none: f32
+}
+
+impl CompiledUniform {
+    pub(crate) const IDENTITY: [[f32; 3]; 3] = [
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 0.0, 1.0],
+    ];
+
+    pub fn leaf(position: [f32; 3], size: f32, material: MaterialUniform) -> Self {
+        Self {
+            position,
+            rotation: Self::IDENTITY,
+            size,
+            material,
+            childs: [0.0; 8],
+            is_leaf: 1.0,
+            is_none: 0.0
+        }
+    }
+
+    pub fn empty(position: [f32; 3], size: f32) -> Self {
+        Self {
+            is_none: 1.0,
+            ..Self::leaf(position, size, MaterialUniform::default())
+        }
+    }
+
+    pub fn branch(position: [f32; 3], size: f32, childs: [f32; 8]) -> Self {
+        Self {
+            childs,
+            is_leaf: 0.0,
+            ..Self::leaf(position, size, MaterialUniform::default())
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(uniform)]
+pub(crate) struct VoxelSpaceUniform {
+    pub(crate) instance_count: u32,
+    pub(crate) tlas_node_count: u32
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(name = "VoxelInstance")]
+pub(crate) struct VoxelInstanceUniform {
+    #[wgsl(transpose)]
+    pub(crate) rotation: [[f32; 3]; 3],
+    pub(crate) position: [f32; 3],
+    pub(crate) scale: f32,
+    pub(crate) root: u32,
+    pub(crate) node_offset: u32,
+    pub(crate) node_count: u32
+}
+
+// Internal nodes keep their two children next to each other at `left` and
+// `left + 1`, leaves point at one object.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod, ToWgslShaderSlice)]
+#[wgsl(name = "TlasNode")]
+pub struct TlasNodeUniform {
+    pub min: [f32; 3],
+    pub left: u32,
+    pub max: [f32; 3],
+    pub object: u32,
+}
@@ -0,0 +1,176 @@
+use super::{child_index, CompiledUniform, MaterialUniform, CHILD_OFFSETS};
+
+#[derive(Debug, Clone, PartialEq)]
+enum BuilderNode {
+    Empty,
+    Leaf(MaterialUniform),
+    Branch(Box<[BuilderNode; 8]>),
+}
+
+impl BuilderNode {
+    fn from_value(value: Option<MaterialUniform>) -> Self {
+        match value {
+            Some(material) => Self::Leaf(material),
+            None => Self::Empty,
+        }
+    }
+
+    fn set(&mut self, origin: [u32; 3], size: u32, pos: [u32; 3], value: Option<MaterialUniform>) {
+        if size == 1 {
+            *self = Self::from_value(value);
+            return;
+        }
+
+        if let Self::Empty | Self::Leaf(_) = self {
+            if *self == Self::from_value(value) {
+                return;
+            }
+
+            let value = self.clone();
+
+            *self = Self::Branch(Box::new([(); 8].map(|_| value.clone())));
+        }
+
+        let Self::Branch(childs) = self else { unreachable!() };
+
+        let half_size = size / 2;
+        let signs = [0, 1, 2].map(|axis| if pos[axis] >= origin[axis] + half_size { 1 } else { -1 });
+        let child = child_index(signs);
+
+        let child_origin = [0, 1, 2].map(|axis| {
+            origin[axis] + if signs[axis] > 0 { half_size } else { 0 }
+        });
+
+        childs[child].set(child_origin, half_size, pos, value);
+
+        if childs.iter().all(|child| *child == childs[0] && !matches!(child, Self::Branch(_))) {
+            *self = childs[0].clone();
+        }
+    }
+
+    fn get(&self, origin: [u32; 3], size: u32, pos: [u32; 3]) -> Option<MaterialUniform> {
+        match self {
+            Self::Empty => None,
+            Self::Leaf(material) => Some(*material),
+            Self::Branch(childs) => {
+                let half_size = size / 2;
+                let signs = [0, 1, 2].map(|axis| if pos[axis] >= origin[axis] + half_size { 1 } else { -1 });
+
+                let child_origin = [0, 1, 2].map(|axis| {
+                    origin[axis] + if signs[axis] > 0 { half_size } else { 0 }
+                });
+
+                childs[child_index(signs)].get(child_origin, half_size, pos)
+            }
+        }
+    }
+
+    fn compile(&self, position: [f32; 3], size: f32, nodes: &mut Vec<CompiledUniform>) -> usize {
+        let node = match self {
+            Self::Empty => CompiledUniform::empty(position, size),
+            Self::Leaf(material) => CompiledUniform::leaf(position, size, *material),
+            Self::Branch(childs) => {
+                let mut out = [0.0; 8];
+
+                for (i, offset) in CHILD_OFFSETS.iter().enumerate() {
+                    let child_position = [0, 1, 2].map(|axis| {
+                        position[axis] + offset[axis] as f32 * size / 2.0
+                    });
+
+                    out[i] = childs[i].compile(child_position, size / 2.0, nodes) as f32;
+                }
+
+                CompiledUniform::branch(position, size, out)
+            }
+        };
+
+        nodes.push(node);
+
+        nodes.len() - 1
+    }
+}
+
+pub struct SvoBuilder {
+    depth: u32,
+    root: BuilderNode,
+}
+
+impl SvoBuilder {
+    // The tree is `2^depth` voxels wide, so `depth` has to be below 32 for the
+    // size to fit in a u32.
+    pub fn new(depth: u32) -> Self {
+        assert!(depth < u32::BITS, "octree depth {} is too large, the size must fit in a u32", depth);
+
+        Self {
+            depth,
+            root: BuilderNode::Empty,
+        }
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn size(&self) -> u32 {
+        1 << self.depth
+    }
+
+    // Panics if a coordinate is not below `size()`, the same for `remove_voxel`.
+    pub fn set_voxel(&mut self, x: u32, y: u32, z: u32, material: MaterialUniform) {
+        self.set([x, y, z], Some(material));
+    }
+
+    pub fn remove_voxel(&mut self, x: u32, y: u32, z: u32) {
+        self.set([x, y, z], None);
+    }
+
+    pub fn get_voxel(&self, x: u32, y: u32, z: u32) -> Option<MaterialUniform> {
+        self.root.get([0; 3], self.size(), [x, y, z])
+    }
+
+    // Voxels are one unit wide and the tree is centered at the origin. Children
+    // are emitted before their parent, so the root is always the last node.
+    pub fn build(&self) -> Vec<CompiledUniform> {
+        let mut nodes = vec![];
+
+        self.root.compile([0.0; 3], self.size() as f32 / 2.0, &mut nodes);
+
+        nodes
+    }
+
+    fn set(&mut self, pos: [u32; 3], value: Option<MaterialUniform>) {
+        let size = self.size();
+
+        assert!(pos.iter().all(|x| *x < size), "voxel {:?} is outside of {}^3 octree", pos, size);
+
+        self.root.set([0; 3], size, pos, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_depth_fits() {
+        let mut builder = SvoBuilder::new(31);
+        let material = MaterialUniform { roughness: 0.5, ..MaterialUniform::default() };
+
+        builder.set_voxel(u32::MAX >> 1, 0, 0, material);
+
+        assert_eq!(builder.get_voxel(u32::MAX >> 1, 0, 0), Some(material));
+        assert_eq!(builder.get_voxel(0, 0, 0), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "too large")]
+    fn rejects_depth_beyond_u32() {
+        SvoBuilder::new(32);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of 4^3 octree")]
+    fn rejects_voxel_outside_tree() {
+        SvoBuilder::new(2).remove_voxel(0, 4, 0);
+    }
+}
@@ -0,0 +1,544 @@
+pub mod builder;
+pub mod raycast;
+pub mod scene;
+pub mod simplify;
+pub mod space;
+pub mod svo;
+pub mod tlas;
+pub mod vox;
+
+use std::{fs, io, ops::Range};
+
+use log::*;
+
+pub use builder::SvoBuilder;
+pub use crate::uniforms::{CompiledUniform, MaterialUniform, MAX_TREE_DEPTH};
+pub use raycast::Hit;
+pub use space::{VoxelInstance, VoxelSpace};
+pub use tlas::Aabb;
+use scene::{parse_scene, SceneError};
+use svo::SvoFile;
+use vox::VoxFile;
+
+pub(crate) const CHILD_OFFSETS: [[i32; 3]; 8] = [
+    [1, 1, 1],
+    [-1, -1, -1],
+    [1, -1, -1],
+    [1, 1, -1],
+    [-1, 1, -1],
+    [1, -1, 1],
+    [-1, -1, 1],
+    [-1, 1, 1],
+];
+
+pub struct VoxelTree {
+    uniform: Vec<CompiledUniform>,
+    root: usize,
+    // Walked once when the nodes are replaced, edits only ever add the level
+    // of the voxel they reach.
+    depth: usize,
+
+    position: [f32; 3],
+    rotation: [[f32; 3]; 3],
+
+    dirty: Vec<usize>,
+    free: Vec<usize>,
+    full_update: bool,
+    moved: bool,
+
+    // File the nodes were loaded from, already in the layout of the storage
+    // buffer. Dropped on the first edit.
+    svo: Option<SvoFile>,
+}
+
+impl VoxelTree {
+    pub fn new() -> Self {
+        Self {
+            uniform: vec![],
+            root: 0,
+            depth: 0,
+
+            position: [0.0; 3],
+            rotation: CompiledUniform::IDENTITY,
+
+            dirty: vec![],
+            free: vec![],
+            full_update: true,
+            moved: true,
+
+            svo: None,
+        }
+    }
+
+    fn replace_nodes(&mut self, nodes: Vec<CompiledUniform>, root: usize) {
+        self.uniform = nodes;
+        self.root = root;
+        self.depth = tree_depth(&self.uniform, root);
+
+        self.dirty.clear();
+        self.free.clear();
+        self.full_update = true;
+
+        self.svo = None;
+    }
+
+    pub fn load(&mut self, file: String) -> Result<(), SceneError> {
+        let file = fs::read_to_string(file)?;
+
+        let nodes = parse_scene(file.as_str())?;
+        let root = find_root(&nodes).unwrap_or(0);
+
+        self.replace_nodes(nodes, root);
+        self.simplify();
+
+        Ok(())
+    }
+
+    pub fn load_vox(&mut self, file: String, model: usize) -> io::Result<()> {
+        let vox = VoxFile::open(file)?;
+
+        let nodes = vox.compile(model)?;
+        let root = nodes.len().saturating_sub(1);
+
+        self.replace_nodes(nodes, root);
+
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_svo(&mut self, file: String) -> io::Result<()> {
+        let svo = SvoFile::open(file)?;
+
+        self.replace_nodes(svo.compile(), svo.root() as usize);
+        self.svo = Some(svo);
+
+        Ok(())
+    }
+
+    pub fn set_nodes(&mut self, nodes: Vec<CompiledUniform>, root: usize) {
+        self.replace_nodes(nodes, root);
+    }
+
+    pub fn nodes(&self) -> &[CompiledUniform] {
+        &self.uniform
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    pub fn simplify(&mut self) -> usize {
+        if self.uniform.is_empty() {
+            return 0;
+        }
+
+        let node_count = self.uniform.len();
+
+        let nodes = simplify::simplify(&self.uniform, self.root);
+        let root = nodes.len() - 1;
+
+        self.replace_nodes(nodes, root);
+
+        let removed = node_count - self.uniform.len();
+
+        debug!("Voxel tree simplified: {} of {} nodes removed", removed, node_count);
+
+        removed
+    }
+
+    // Voxels use the same grid as `SvoBuilder`: one unit wide, counted from the
+    // minimum corner of the root cube. Returns false if the voxel is outside
+    // of the tree.
+    pub fn set_voxel(&mut self, x: u32, y: u32, z: u32, material: MaterialUniform) -> bool {
+        self.edit([x, y, z], Some(material))
+    }
+
+    pub fn clear_voxel(&mut self, x: u32, y: u32, z: u32) -> bool {
+        self.edit([x, y, z], None)
+    }
+
+    // Edits assume that no node is shared between several parents, which holds
+    // for every tree built by `SvoBuilder`, `simplify` or the vox loader.
+    fn edit(&mut self, pos: [u32; 3], value: Option<MaterialUniform>) -> bool {
+        let Some(root) = self.uniform.get(self.root).copied() else {
+            return false;
+        };
+
+        let point = [0, 1, 2].map(|axis| root.position[axis] - root.size + pos[axis] as f32 + 0.5);
+
+        if (0..3).any(|axis| (point[axis] - root.position[axis]).abs() > root.size) {
+            return false;
+        }
+
+        self.svo = None;
+
+        let target = match value {
+            Some(material) => CompiledUniform::leaf([0.0; 3], 0.0, material),
+            None => CompiledUniform::empty([0.0; 3], 0.0),
+        };
+
+        let mut path = vec![];
+        let mut index = self.root;
+
+        while self.uniform[index].size > 0.5 {
+            let node = self.uniform[index];
+
+            if node.is_leaf >= 1.0 {
+                if node.is_none == target.is_none && (node.is_none >= 1.0 || node.material == target.material) {
+                    return true;
+                }
+
+                self.subdivide(index);
+            }
+
+            let node = self.uniform[index];
+            let signs = [0, 1, 2].map(|axis| if point[axis] >= node.position[axis] { 1 } else { -1 });
+
+            path.push(index);
+            index = node.childs[child_index(signs)] as usize;
+        }
+
+        self.depth = self.depth.max(path.len());
+
+        let node = self.uniform[index];
+
+        self.uniform[index] = CompiledUniform {
+            position: node.position,
+            size: node.size,
+            ..target
+        };
+        self.dirty.push(index);
+
+        for parent in path.into_iter().rev() {
+            let node = self.uniform[parent];
+            let childs = node.childs.map(|child| self.uniform[child as usize]);
+
+            let Some(merged) = simplify::merged(&node, &childs) else {
+                break;
+            };
+
+            self.uniform[parent] = merged;
+            self.dirty.push(parent);
+            self.free.extend(node.childs.map(|child| child as usize));
+        }
+
+        true
+    }
+
+    // Splits a leaf into eight copies of itself, reusing the slots of nodes
+    // dropped by earlier edits before growing the array.
+    fn subdivide(&mut self, index: usize) {
+        let node = self.uniform[index];
+        let mut childs = [0.0; 8];
+
+        for (i, offset) in CHILD_OFFSETS.iter().enumerate() {
+            let child = CompiledUniform {
+                position: [0, 1, 2].map(|axis| node.position[axis] + offset[axis] as f32 * node.size / 2.0),
+                size: node.size / 2.0,
+                ..node
+            };
+
+            let child_index = match self.free.pop() {
+                Some(free) => {
+                    self.uniform[free] = child;
+                    free
+                },
+                None => {
+                    self.uniform.push(child);
+                    self.uniform.len() - 1
+                }
+            };
+
+            childs[i] = child_index as f32;
+            self.dirty.push(child_index);
+        }
+
+        self.uniform[index] = CompiledUniform {
+            childs,
+            is_leaf: 0.0,
+            is_none: 0.0,
+            ..node
+        };
+        self.dirty.push(index);
+    }
+
+    // Same convention as the node fields: a point `p` is at
+    // `rotation * (p - position)` in the space of the tree. Instances of the
+    // tree place it relative to this transform, so with `VoxelInstance::new`
+    // the tree is where it would be without instancing.
+    pub fn set_transform(&mut self, position: [f32; 3], rotation: [[f32; 3]; 3]) {
+        self.position = position;
+        self.rotation = rotation;
+        self.moved = true;
+    }
+
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    pub fn rotation(&self) -> [[f32; 3]; 3] {
+        self.rotation
+    }
+
+    // Bounds of the root cube after the transform of the tree, None for an
+    // empty tree.
+    pub fn bounds(&self) -> Option<Aabb> {
+        let root = self.uniform.get(self.root)?;
+
+        let cube = Aabb {
+            min: [-root.size; 3],
+            max: [root.size; 3],
+        };
+
+        Some(cube.transform(root.rotation, root.position).transform(self.rotation, self.position))
+    }
+
+    // Levels below the root, 0 for a tree of a single leaf. Merges after an
+    // edit are not counted back, so it may be larger than the tree is now.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Casts a ray in the space of the tree, `dir` does not have to be
+    // normalized and the hit fraction is measured in its lengths.
+    pub fn raycast(&self, origin: [f32; 3], dir: [f32; 3]) -> Option<Hit> {
+        raycast::raycast(&self.uniform, self.root, origin, dir)
+    }
+
+    // Bytes of every node as the storage buffer holds them, if they can be
+    // uploaded without serialising the nodes again.
+    pub(crate) fn wgsl_bytes(&self) -> Option<&[u8]> {
+        self.svo.as_ref().map(SvoFile::node_bytes)
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.full_update || self.moved || !self.dirty.is_empty()
+    }
+
+    // Returns the node ranges changed since the last call, consecutive dirty
+    // nodes are merged into one range.
+    pub(crate) fn take_changes(&mut self) -> Vec<Range<usize>> {
+        let mut ranges = vec![];
+
+        if self.full_update {
+            ranges.push(0..self.uniform.len());
+        } else {
+            self.dirty.sort_unstable();
+            self.dirty.dedup();
+
+            let mut start = 0;
+
+            for i in 1..=self.dirty.len() {
+                if i == self.dirty.len() || self.dirty[i] != self.dirty[i - 1] + 1 {
+                    ranges.push(self.dirty[start]..self.dirty[i - 1] + 1);
+                    start = i;
+                }
+            }
+        }
+
+        self.full_update = false;
+        self.moved = false;
+        self.dirty.clear();
+
+        ranges
+    }
+}
+
+impl Default for VoxelTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn child_index(signs: [i32; 3]) -> usize {
+    CHILD_OFFSETS.iter().position(|offset| *offset == signs).unwrap()
+}
+
+fn tree_depth(nodes: &[CompiledUniform], root: usize) -> usize {
+    let mut depth = 0;
+    let mut stack = vec![(root, 0)];
+
+    while let Some((index, level)) = stack.pop() {
+        let Some(node) = nodes.get(index) else {
+            continue;
+        };
+
+        depth = depth.max(level);
+
+        // A cycle would never end otherwise.
+        if node.is_leaf < 1.0 && level < nodes.len() {
+            stack.extend(node.childs.map(|child| (child as usize, level + 1)));
+        }
+    }
+
+    depth
+}
+
+pub fn find_root(nodes: &[CompiledUniform]) -> Option<usize> {
+    let mut is_child = vec![false; nodes.len()];
+
+    for node in nodes.iter().filter(|node| node.is_leaf < 1.0) {
+        for child in node.childs {
+            if let Some(is_child) = is_child.get_mut(child as usize) {
+                *is_child = true;
+            }
+        }
+    }
+
+    is_child.iter().position(|is_child| !is_child)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same xorshift as the raycast tests.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn float(&mut self, min: f32, max: f32) -> f32 {
+            min + (self.next() % 10000) as f32 / 10000.0 * (max - min)
+        }
+    }
+
+    fn material(id: u32) -> MaterialUniform {
+        MaterialUniform {
+            emmitance: [id as f32, 0.0, 0.0],
+            ..MaterialUniform::default()
+        }
+    }
+
+    fn tree(builder: &SvoBuilder) -> VoxelTree {
+        let nodes = builder.build();
+        let mut tree = VoxelTree::new();
+
+        tree.set_nodes(nodes.clone(), nodes.len() - 1);
+        tree
+    }
+
+    #[test]
+    fn edits_deepen_tree_to_voxel_level() {
+        let mut tree = tree(&SvoBuilder::new(3));
+
+        assert_eq!(tree.depth(), 0);
+
+        tree.set_voxel(1, 2, 3, MaterialUniform::default());
+
+        assert_eq!(tree.depth(), 3);
+        assert_eq!(tree.depth(), tree_depth(tree.nodes(), tree.root()));
+    }
+
+    // Casts rays from outside the tree at random points inside it and checks
+    // that the edited tree shows the same voxels as one built from scratch.
+    fn assert_same_voxels(rng: &mut Rng, tree: &VoxelTree, builder: &SvoBuilder) {
+        let nodes = builder.build();
+        let half = builder.size() as f32 / 2.0;
+
+        for _ in 0..200 {
+            let origin = [0, 1, 2].map(|_| rng.float(-half * 3.0, half * 3.0) + if rng.next().is_multiple_of(2) { half * 3.0 } else { -half * 3.0 });
+            let target = [0, 1, 2].map(|_| rng.float(-half, half));
+            let dir = [0, 1, 2].map(|axis| target[axis] - origin[axis]);
+
+            let edited = tree.raycast(origin, dir);
+            let built = raycast::raycast(&nodes, nodes.len() - 1, origin, dir);
+
+            match (edited, built) {
+                (None, None) => {},
+                (Some(edited), Some(built)) => {
+                    assert!((edited.fraction - built.fraction).abs() < 1e-4, "{:?} {:?}: {} != {}", origin, dir, edited.fraction, built.fraction);
+                    assert_eq!(edited.material, built.material, "{:?} {:?}", origin, dir);
+                },
+                (edited, built) => panic!("{:?} {:?}: edited {:?}, built {:?}", origin, dir, edited.is_some(), built.is_some()),
+            }
+        }
+    }
+
+    #[test]
+    fn edits_match_builder() {
+        let mut rng = Rng(0x1234_5678);
+
+        for depth in 1..=4 {
+            let mut builder = SvoBuilder::new(depth);
+            let size = builder.size();
+
+            for _ in 0..size * size {
+                builder.set_voxel(rng.next() % size, rng.next() % size, rng.next() % size, material(rng.next() % 3));
+            }
+
+            let mut tree = tree(&builder);
+
+            for _ in 0..20 {
+                for _ in 0..size * 2 {
+                    let [x, y, z] = [0, 1, 2].map(|_| rng.next() % size);
+
+                    if rng.next().is_multiple_of(3) {
+                        assert!(tree.clear_voxel(x, y, z));
+                        builder.remove_voxel(x, y, z);
+                    } else {
+                        let material = material(rng.next() % 3);
+
+                        assert!(tree.set_voxel(x, y, z, material));
+                        builder.set_voxel(x, y, z, material);
+                    }
+                }
+
+                assert_same_voxels(&mut rng, &tree, &builder);
+            }
+        }
+    }
+
+    #[test]
+    fn edits_outside_tree_are_rejected() {
+        let mut tree = tree(&SvoBuilder::new(2));
+
+        assert!(!tree.set_voxel(4, 0, 0, material(1)));
+        assert!(!tree.clear_voxel(0, 0, 4));
+    }
+
+    #[test]
+    fn merged_nodes_are_reused() {
+        let mut tree = tree(&SvoBuilder::new(3));
+
+        tree.set_voxel(1, 2, 3, material(1));
+
+        let len = tree.nodes().len();
+
+        // Clearing merges the branches back into the root, their slots are
+        // taken again by the next edit.
+        tree.clear_voxel(1, 2, 3);
+        tree.set_voxel(6, 5, 4, material(2));
+
+        assert_eq!(tree.nodes().len(), len);
+    }
+
+    #[test]
+    fn changes_are_merged_into_ranges() {
+        let mut tree = tree(&SvoBuilder::new(2));
+
+        let changes = tree.take_changes();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0], 0..tree.nodes().len());
+        assert!(!tree.is_dirty());
+
+        tree.dirty.extend([9, 4, 3, 5, 12, 10, 4]);
+
+        assert_eq!(tree.take_changes(), [3..6, 9..11, 12..13]);
+        assert!(tree.take_changes().is_empty());
+
+        tree.set_voxel(0, 0, 0, material(1));
+
+        let changes = tree.take_changes();
+
+        assert!(!changes.is_empty());
+        assert!(changes.windows(2).all(|pair| pair[0].end < pair[1].start));
+        assert!(changes.iter().all(|range| range.end <= tree.nodes().len()));
+    }
+}
@@ -0,0 +1,236 @@
+use super::{CompiledUniform, MaterialUniform};
+use crate::uniforms::TREE_STACK_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub fraction: f32,
+    pub normal: [f32; 3],
+    pub material: MaterialUniform,
+    pub node: usize,
+}
+
+// Same math as `box_int` in render_def.wgsl: the ray is moved into the space of
+// the cube and clipped against its slabs. Returns the entry distance and the
+// normal of the entry face.
+pub fn box_intersect(ro: [f32; 3], rd: [f32; 3], cube: &CompiledUniform) -> Option<(f32, [f32; 3])> {
+    let rot = cube.rotation;
+    let rel = [0, 1, 2].map(|axis| ro[axis] - cube.position[axis]);
+
+    let rd = [0, 1, 2].map(|r| (0..3).map(|c| rot[r][c] * rd[c]).sum::<f32>());
+    let ro = [0, 1, 2].map(|r| (0..3).map(|c| rot[r][c] * rel[c]).sum::<f32>());
+
+    let s = rd.map(|x| if x < 0.0 { 1.0 } else { -1.0 });
+
+    let t1 = [0, 1, 2].map(|axis| (-ro[axis] + s[axis] * cube.size) / rd[axis]);
+    let t2 = [0, 1, 2].map(|axis| (-ro[axis] - s[axis] * cube.size) / rd[axis]);
+
+    let t_near = t1[0].max(t1[1]).max(t1[2]);
+    let t_far = t2[0].min(t2[1]).min(t2[2]);
+
+    if t_near > t_far || t_far < 0.0 {
+        return None;
+    }
+
+    let axis = if t1[0] > t1[1] && t1[0] > t1[2] {
+        0
+    } else if t1[1] > t1[2] {
+        1
+    } else {
+        2
+    };
+
+    Some((t_near, rot[axis].map(|x| x * s[axis])))
+}
+
+// CPU version of `cast_ray_tree` in ray_casting.wgsl, front to back with an
+// explicit stack. Works in the space of the tree.
+pub fn raycast(nodes: &[CompiledUniform], root: usize, ro: [f32; 3], rd: [f32; 3]) -> Option<Hit> {
+    let mut out: Option<Hit> = None;
+    // Never outgrows the shader stack for trees up to `MAX_TREE_DEPTH`.
+    let mut stack = Vec::with_capacity(TREE_STACK_SIZE);
+    stack.push((root, f32::NEG_INFINITY));
+
+    let closest = |out: &Option<Hit>| out.map_or(f32::INFINITY, |hit| hit.fraction);
+
+    while let Some((index, fraction)) = stack.pop() {
+        if fraction >= closest(&out) {
+            continue;
+        }
+
+        let node = nodes.get(index)?;
+
+        let Some((fraction, normal)) = box_intersect(ro, rd, node) else {
+            continue;
+        };
+
+        if fraction >= closest(&out) || node.is_none >= 1.0 {
+            continue;
+        }
+
+        if node.is_leaf >= 1.0 {
+            out = Some(Hit { fraction, normal, material: node.material, node: index });
+            continue;
+        }
+
+        let mut childs = node.childs.iter()
+            .map(|child| *child as usize)
+            .filter_map(|child| {
+                let node = nodes.get(child)?;
+                let (fraction, _) = box_intersect(ro, rd, node)?;
+
+                (node.is_none < 1.0).then_some((child, fraction))
+            })
+            .collect::<Vec<_>>();
+
+        childs.sort_by(|a, b| b.1.total_cmp(&a.1));
+        stack.extend(childs);
+    }
+
+    out
+}
+
+// Tests every reachable leaf without any ordering, slow but simple enough to
+// check `raycast` and the shader against.
+pub fn raycast_reference(nodes: &[CompiledUniform], root: usize, ro: [f32; 3], rd: [f32; 3]) -> Option<Hit> {
+    let node = nodes.get(root)?;
+
+    if node.is_leaf >= 1.0 {
+        if node.is_none >= 1.0 {
+            return None;
+        }
+
+        let (fraction, normal) = box_intersect(ro, rd, node)?;
+
+        return Some(Hit { fraction, normal, material: node.material, node: root });
+    }
+
+    node.childs.iter()
+        .filter_map(|child| raycast_reference(nodes, *child as usize, ro, rd))
+        .min_by(|a, b| a.fraction.total_cmp(&b.fraction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::SvoBuilder;
+
+    // Small xorshift so the tests don't need a rand dependency.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn float(&mut self, min: f32, max: f32) -> f32 {
+            min + (self.next() % 10000) as f32 / 10000.0 * (max - min)
+        }
+    }
+
+    fn material(id: u32) -> MaterialUniform {
+        MaterialUniform {
+            emmitance: [id as f32, 0.0, 0.0],
+            ..MaterialUniform::default()
+        }
+    }
+
+    fn random_tree(rng: &mut Rng, depth: u32, fill: u32) -> Vec<CompiledUniform> {
+        let mut builder = SvoBuilder::new(depth);
+        let size = builder.size();
+
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    if rng.next() % 100 < fill {
+                        builder.set_voxel(x, y, z, material(rng.next() % 4));
+                    }
+                }
+            }
+        }
+
+        builder.build()
+    }
+
+    fn assert_same(nodes: &[CompiledUniform], ro: [f32; 3], rd: [f32; 3]) {
+        let root = nodes.len() - 1;
+
+        let hit = raycast(nodes, root, ro, rd);
+        let reference = raycast_reference(nodes, root, ro, rd);
+
+        // Rays through an edge can enter two leaves at the same fraction, so
+        // only the distance has to agree.
+        match (hit, reference) {
+            (None, None) => {},
+            (Some(hit), Some(reference)) => assert!(
+                (hit.fraction - reference.fraction).abs() < 1e-4,
+                "ray {:?} {:?}: hit at {} instead of {}", ro, rd, hit.fraction, reference.fraction
+            ),
+            _ => panic!("ray {:?} {:?}: {:?} instead of {:?}", ro, rd, hit, reference),
+        }
+    }
+
+    #[test]
+    fn matches_reference_on_random_trees() {
+        let mut rng = Rng(0x1234_5678);
+
+        for depth in 1..=4 {
+            for fill in [5, 30, 70] {
+                let nodes = random_tree(&mut rng, depth, fill);
+                let extent = (1 << depth) as f32;
+
+                for _ in 0..500 {
+                    let ro = [0; 3].map(|_| rng.float(-2.0 * extent, 2.0 * extent));
+                    let target = [0; 3].map(|_| rng.float(-extent / 2.0, extent / 2.0));
+                    let rd = [0, 1, 2].map(|axis| target[axis] - ro[axis]);
+
+                    assert_same(&nodes, ro, rd);
+                }
+            }
+        }
+    }
+
+    // The near child is a branch, but the ray only clips its empty corner and
+    // has to go on to the far child behind it.
+    #[test]
+    fn passes_through_empty_corner_of_near_child() {
+        let mut builder = SvoBuilder::new(2);
+
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    if [x, y, z] != [1, 1, 1] {
+                        builder.set_voxel(x, y, z, material(1));
+                    }
+
+                    builder.set_voxel(x + 2, y, z, material(2));
+                }
+            }
+        }
+
+        let nodes = builder.build();
+        let root = nodes.len() - 1;
+
+        // Enters the near child from above through its corner voxel (1, 1, 1),
+        // which spans [-1, 0] on every axis, and leaves it through x = 0.
+        let ro = [-0.8, 0.5, -0.5];
+        let rd = [1.0, -1.0, 0.0];
+
+        assert_same(&nodes, ro, rd);
+
+        let hit = raycast(&nodes, root, ro, rd).expect("ray has to hit the far child");
+
+        assert!((hit.fraction - 0.8).abs() < 1e-4, "hit at {}", hit.fraction);
+        assert_eq!(hit.material, material(2));
+        assert_eq!(hit.normal, [-1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn misses_empty_tree() {
+        let nodes = SvoBuilder::new(3).build();
+
+        assert_eq!(raycast(&nodes, nodes.len() - 1, [-10.0, 0.0, 0.0], [1.0, 0.0, 0.0]), None);
+    }
+}
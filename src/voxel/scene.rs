@@ -0,0 +1,283 @@
+use std::{error, fmt, io};
+
+use serde::Deserialize;
+
+use super::{CompiledUniform, MaterialUniform};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SceneMaterial {
+    pub emmitance: Vec<f32>,
+    pub reflectance: Vec<f32>,
+    pub roughness: f32,
+    pub opacity: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SceneNode {
+    pub pos: Vec<f32>,
+    pub rot: Vec<Vec<f32>>,
+    pub size: f32,
+    pub material: SceneMaterial,
+    pub childs: Vec<u32>,
+    pub is_leaf: u32,
+    pub is_none: u32,
+}
+
+impl SceneNode {
+    const FIELDS: [&'static str; 7] = ["pos", "rot", "size", "material", "childs", "is_leaf", "is_none"];
+
+    fn compile(&self, index: usize) -> Result<CompiledUniform, SceneError> {
+        let rot = array::<3, _>(index, "rot", &self.rot)?;
+
+        Ok(CompiledUniform {
+            position: *array(index, "pos", &self.pos)?,
+            rotation: [
+                *array(index, "rot", &rot[0])?,
+                *array(index, "rot", &rot[1])?,
+                *array(index, "rot", &rot[2])?,
+            ],
+            size: self.size,
+            material: MaterialUniform {
+                emmitance: *array(index, "material.emmitance", &self.material.emmitance)?,
+                reflectance: *array(index, "material.reflectance", &self.material.reflectance)?,
+                roughness: self.material.roughness,
+                opacity: self.material.opacity,
+            },
+            childs: array::<8, _>(index, "childs", &self.childs)?.map(|child| child as f32),
+            is_leaf: self.is_leaf as f32,
+            is_none: self.is_none as f32
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Node { index: usize, field: String, source: serde_json::Error },
+    MissingField { index: usize, field: &'static str },
+    WrongLength { index: usize, field: &'static str, expected: usize, found: usize },
+    ChildOutOfRange { index: usize, child: usize, value: u32, len: usize },
+    Cycle { index: usize },
+    TooLarge { nodes: usize, size: u64, max: u64 },
+    TooDeep { tree: usize, depth: usize, max: usize },
+    InvalidScale { instance: usize, scale: f32 },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read scene: {}", e),
+            Self::Json(e) => write!(f, "scene is not a valid json array: {}", e),
+            Self::Node { index, field, source } => write!(f, "node {}: field `{}`: {}", index, field, source),
+            Self::MissingField { index, field } => write!(f, "node {}: missing field `{}`", index, field),
+            Self::WrongLength { index, field, expected, found } => write!(f,
+                "node {}: field `{}` has {} elements, expected {}", index, field, found, expected
+            ),
+            Self::ChildOutOfRange { index, child, value, len } => write!(f,
+                "node {}: child {} points to node {}, but the scene has only {} nodes", index, child, value, len
+            ),
+            Self::Cycle { index } => write!(f, "node {}: node is its own ancestor", index),
+            Self::TooLarge { nodes, size, max } => write!(f,
+                "scene with {} nodes needs {} bytes, but the device allows storage buffers of {} bytes", nodes, size, max
+            ),
+            Self::TooDeep { tree, depth, max } => write!(f,
+                "tree {} is {} levels deep, but the shader can only walk {} levels", tree, depth, max
+            ),
+            Self::InvalidScale { instance, scale } => write!(f, "instance {}: scale {} is not positive", instance, scale),
+        }
+    }
+}
+
+impl error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json(e) | Self::Node { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+pub fn parse_scene(source: &str) -> Result<Vec<CompiledUniform>, SceneError> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(source)
+        .map_err(SceneError::Json)?;
+
+    let mut nodes = Vec::with_capacity(values.len());
+
+    for (index, value) in values.into_iter().enumerate() {
+        if let Some(object) = value.as_object() {
+            if let Some(field) = SceneNode::FIELDS.into_iter().find(|field| !object.contains_key(*field)) {
+                return Err(SceneError::MissingField { index, field });
+            }
+        }
+
+        let node: SceneNode = serde_path_to_error::deserialize(value)
+            .map_err(|e| SceneError::Node { index, field: e.path().to_string(), source: e.into_inner() })?;
+
+        nodes.push(node.compile(index)?);
+    }
+
+    validate_childs(&nodes)?;
+
+    Ok(nodes)
+}
+
+fn validate_childs(nodes: &[CompiledUniform]) -> Result<(), SceneError> {
+    for (index, node) in nodes.iter().enumerate() {
+        if node.is_leaf >= 1.0 {
+            continue;
+        }
+
+        for (child, value) in node.childs.iter().enumerate() {
+            if *value as usize >= nodes.len() {
+                return Err(SceneError::ChildOutOfRange { index, child, value: *value as u32, len: nodes.len() });
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum State { New, Open, Done }
+
+    let mut states = vec![State::New; nodes.len()];
+
+    for start in 0..nodes.len() {
+        if states[start] != State::New {
+            continue;
+        }
+
+        let mut stack = vec![(start, 0)];
+        states[start] = State::Open;
+
+        while let Some((index, child)) = stack.pop() {
+            let node = &nodes[index];
+
+            if node.is_leaf >= 1.0 || child == 8 {
+                states[index] = State::Done;
+                continue;
+            }
+
+            stack.push((index, child + 1));
+
+            let next = node.childs[child] as usize;
+
+            match states[next] {
+                State::New => {
+                    states[next] = State::Open;
+                    stack.push((next, 0));
+                },
+                State::Open => return Err(SceneError::Cycle { index: next }),
+                State::Done => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn array<'a, const N: usize, T>(index: usize, field: &'static str, values: &'a [T]) -> Result<&'a [T; N], SceneError> {
+    values.try_into()
+        .map_err(|_| SceneError::WrongLength { index, field, expected: N, found: values.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn leaf() -> Value {
+        json!({
+            "pos": [0.0, 0.0, 0.0],
+            "rot": [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            "size": 1.0,
+            "material": { "emmitance": [0.0, 0.0, 0.0], "reflectance": [1.0, 1.0, 1.0], "roughness": 0.5, "opacity": 0.0 },
+            "childs": [0, 0, 0, 0, 0, 0, 0, 0],
+            "is_leaf": 1,
+            "is_none": 0
+        })
+    }
+
+    fn branch(childs: [u32; 8]) -> Value {
+        let mut node = leaf();
+
+        node["childs"] = json!(childs);
+        node["is_leaf"] = json!(0);
+        node
+    }
+
+    fn parse(nodes: Vec<Value>) -> Result<Vec<CompiledUniform>, SceneError> {
+        parse_scene(&Value::Array(nodes).to_string())
+    }
+
+    #[test]
+    fn parses_valid_scene() {
+        let nodes = parse(vec![leaf(), branch([0; 8])]).unwrap();
+
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1].childs, [0.0; 8]);
+        assert_eq!(nodes[0].material.roughness, 0.5);
+    }
+
+    #[test]
+    fn reports_missing_field() {
+        let mut node = leaf();
+
+        node.as_object_mut().unwrap().remove("childs");
+
+        assert!(matches!(parse(vec![leaf(), node]), Err(SceneError::MissingField { index: 1, field: "childs" })));
+    }
+
+    #[test]
+    fn reports_wrong_length() {
+        let mut node = leaf();
+
+        node["pos"] = json!([0.0, 0.0]);
+
+        assert!(matches!(
+            parse(vec![node]),
+            Err(SceneError::WrongLength { index: 0, field: "pos", expected: 3, found: 2 })
+        ));
+    }
+
+    #[test]
+    fn reports_wrong_type_with_field() {
+        let mut node = leaf();
+
+        node["material"]["roughness"] = json!("rough");
+
+        let error = parse(vec![leaf(), node]).unwrap_err();
+
+        assert!(matches!(&error, SceneError::Node { index: 1, field, .. } if field == "material.roughness"), "{}", error);
+        assert!(error.to_string().starts_with("node 1: field `material.roughness`: "), "{}", error);
+    }
+
+    #[test]
+    fn reports_child_out_of_range() {
+        let mut childs = [0; 8];
+
+        childs[3] = 5;
+
+        assert!(matches!(
+            parse(vec![leaf(), branch(childs)]),
+            Err(SceneError::ChildOutOfRange { index: 1, child: 3, value: 5, len: 2 })
+        ));
+    }
+
+    #[test]
+    fn reports_cycle() {
+        let mut childs = [0; 8];
+
+        childs[7] = 2;
+
+        let error = parse(vec![leaf(), branch(childs), branch([1; 8])]).unwrap_err();
+
+        assert!(matches!(error, SceneError::Cycle { .. }), "{}", error);
+    }
+}
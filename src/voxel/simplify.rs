@@ -0,0 +1,184 @@
+use super::CompiledUniform;
+
+// Collapses branches whose eight children are all empty, or all leaves with
+// the same material, and drops every node that is no longer reachable from
+// `root`. Children are emitted before their parent, so the root of the
+// returned array is its last node.
+pub fn simplify(nodes: &[CompiledUniform], root: usize) -> Vec<CompiledUniform> {
+    let mut nodes = nodes.to_vec();
+    let mut visited = vec![false; nodes.len()];
+
+    merge(&mut nodes, root, &mut visited);
+
+    let mut out = Vec::new();
+    let mut remap = vec![None; nodes.len()];
+
+    compact(&nodes, root, &mut remap, &mut out);
+
+    out
+}
+
+fn merge(nodes: &mut [CompiledUniform], index: usize, visited: &mut [bool]) {
+    if visited[index] {
+        return;
+    }
+
+    visited[index] = true;
+
+    let node = nodes[index];
+
+    if node.is_leaf >= 1.0 {
+        return;
+    }
+
+    for child in node.childs {
+        merge(nodes, child as usize, visited);
+    }
+
+    let childs = node.childs.map(|child| nodes[child as usize]);
+
+    if let Some(merged) = merged(&node, &childs) {
+        nodes[index] = merged;
+    }
+}
+
+// Returns the leaf that can replace `node` when its children are all empty,
+// or all filled with the same material.
+pub(crate) fn merged(node: &CompiledUniform, childs: &[CompiledUniform; 8]) -> Option<CompiledUniform> {
+    let first = childs[0];
+
+    if childs.iter().any(|child| child.is_leaf < 1.0) {
+        return None;
+    }
+
+    let is_empty = childs.iter().all(|child| child.is_none >= 1.0);
+    let is_uniform = childs.iter().all(|child| child.is_none < 1.0 && child.material == first.material);
+
+    if !is_empty && !is_uniform {
+        return None;
+    }
+
+    Some(CompiledUniform {
+        material: if is_empty { node.material } else { first.material },
+        childs: [0.0; 8],
+        is_leaf: 1.0,
+        is_none: if is_empty { 1.0 } else { 0.0 },
+        ..*node
+    })
+}
+
+fn compact(
+    nodes: &[CompiledUniform],
+    index: usize,
+    remap: &mut [Option<usize>],
+    out: &mut Vec<CompiledUniform>,
+) -> usize {
+    if let Some(new_index) = remap[index] {
+        return new_index;
+    }
+
+    let mut node = nodes[index];
+
+    if node.is_leaf < 1.0 {
+        node.childs = node.childs.map(|child| compact(nodes, child as usize, remap, out) as f32);
+    }
+
+    out.push(node);
+    remap[index] = Some(out.len() - 1);
+
+    out.len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::{MaterialUniform, SvoBuilder};
+
+    fn material(id: u32) -> MaterialUniform {
+        MaterialUniform {
+            emmitance: [id as f32, 0.0, 0.0],
+            ..MaterialUniform::default()
+        }
+    }
+
+    // Branch at the origin over the eight nodes starting at `first`.
+    fn branch(first: usize) -> CompiledUniform {
+        CompiledUniform::branch([0.0; 3], 1.0, std::array::from_fn(|i| (first + i) as f32))
+    }
+
+    #[test]
+    fn same_leaves_collapse_into_parent() {
+        let mut nodes = vec![CompiledUniform::leaf([0.0; 3], 0.5, material(1)); 8];
+        nodes.push(branch(0));
+
+        let out = simplify(&nodes, 8);
+
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].is_leaf, 1.0);
+        assert_eq!(out[0].is_none, 0.0);
+        assert_eq!(out[0].material, material(1));
+        assert_eq!(out[0].size, 1.0);
+    }
+
+    #[test]
+    fn empty_leaves_collapse_into_empty_parent() {
+        let mut nodes = vec![CompiledUniform::empty([0.0; 3], 0.5); 8];
+        nodes.push(branch(0));
+
+        let out = simplify(&nodes, 8);
+
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].is_leaf, out[0].is_none), (1.0, 1.0));
+    }
+
+    #[test]
+    fn merged_child_stays_under_mixed_parent() {
+        // Node 8 has eight equal leaves, node 17 mixes it with empty ones and
+        // stays a branch with only leaves below it.
+        let mut nodes = vec![CompiledUniform::leaf([0.0; 3], 0.25, material(2)); 8];
+        nodes.push(branch(0));
+        nodes.extend(vec![CompiledUniform::empty([0.0; 3], 0.5); 8]);
+
+        let mut root = branch(9);
+        root.childs[3] = 8.0;
+        nodes.push(root);
+
+        let out = simplify(&nodes, 17);
+        let root = out[out.len() - 1];
+
+        assert_eq!(out.len(), 9);
+        assert_eq!(root.is_leaf, 0.0);
+
+        for (i, child) in root.childs.iter().enumerate() {
+            let child = out[*child as usize];
+
+            assert_eq!(child.is_leaf, 1.0);
+            assert_eq!(child.is_none, if i == 3 { 0.0 } else { 1.0 });
+        }
+    }
+
+    #[test]
+    fn drops_unreachable_nodes() {
+        let mut nodes = vec![CompiledUniform::leaf([0.0; 3], 1.0, material(1))];
+        nodes.extend(vec![CompiledUniform::empty([0.0; 3], 1.0); 3]);
+
+        let out = simplify(&nodes, 0);
+
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].material, material(1));
+    }
+
+    #[test]
+    fn builder_trees_are_already_simple() {
+        let mut builder = SvoBuilder::new(3);
+
+        builder.set_voxel(1, 2, 3, material(1));
+        builder.set_voxel(7, 7, 7, material(2));
+
+        let nodes = builder.build();
+        let out = simplify(&nodes, nodes.len() - 1);
+
+        assert_eq!(out.len(), nodes.len());
+        assert_eq!(out[out.len() - 1].is_leaf, 0.0);
+    }
+}
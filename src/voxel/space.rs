@@ -0,0 +1,608 @@
+use std::ops::Range;
+
+use wgpu::*;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use log::*;
+
+use crate::App;
+use super::{tlas, CompiledUniform, Hit, VoxelTree, MAX_TREE_DEPTH};
+use super::scene::SceneError;
+use super::tlas::{Aabb, TlasNodeUniform};
+use crate::uniforms::{VoxelInstanceUniform, VoxelSpaceUniform};
+
+// Places a tree in the scene. A world space point `p` is at
+// `rotation * (p - position) / scale` relative to the transform of the tree,
+// the same transform `box_int` applies with `cube.rotation`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxelInstance {
+    pub tree: usize,
+    pub position: [f32; 3],
+    pub rotation: [[f32; 3]; 3],
+    pub scale: f32,
+}
+
+impl VoxelInstance {
+    pub fn new(tree: usize) -> Self {
+        Self {
+            tree,
+            position: [0.0; 3],
+            rotation: CompiledUniform::IDENTITY,
+            scale: 1.0,
+        }
+    }
+
+    // World space bounds of the instance.
+    pub fn bounds(&self, tree: &VoxelTree) -> Option<Aabb> {
+        Some(tree.bounds()?.scale(self.scale).transform(self.rotation, self.position))
+    }
+
+    // The instance and the tree transform as one, world space points are at
+    // `rotation * (p - position) / scale` in the space of the tree. Only this
+    // one is uploaded.
+    pub(crate) fn world_to_tree(&self, tree: &VoxelTree) -> ([[f32; 3]; 3], [f32; 3], f32) {
+        let (tree_rot, tree_pos) = (tree.rotation(), tree.position());
+        let rot = self.rotation;
+
+        let rotation = [0, 1, 2].map(|r| [0, 1, 2].map(|c| (0..3).map(|k| tree_rot[r][k] * rot[k][c]).sum::<f32>()));
+        let position = [0, 1, 2].map(|i| {
+            self.position[i] + self.scale * (0..3).map(|k| rot[k][i] * tree_pos[k]).sum::<f32>()
+        });
+
+        (rotation, position, self.scale)
+    }
+
+    // Casts a world space ray against the tree the same way `cast_ray_tree`
+    // does, the hit normal is returned in world space.
+    pub fn raycast(&self, tree: &VoxelTree, origin: [f32; 3], dir: [f32; 3]) -> Option<Hit> {
+        let (rot, position, scale) = self.world_to_tree(tree);
+        let rel = [0, 1, 2].map(|axis| origin[axis] - position[axis]);
+
+        let origin = [0, 1, 2].map(|r| (0..3).map(|c| rot[r][c] * rel[c]).sum::<f32>() / scale);
+        let dir = [0, 1, 2].map(|r| (0..3).map(|c| rot[r][c] * dir[c]).sum::<f32>() / scale);
+
+        let hit = tree.raycast(origin, dir)?;
+
+        Some(Hit {
+            normal: [0, 1, 2].map(|c| (0..3).map(|r| rot[r][c] * hit.normal[r]).sum::<f32>()),
+            ..hit
+        })
+    }
+}
+
+pub struct VoxelSpace {
+    trees: Vec<VoxelTree>,
+    slots: Vec<Range<usize>>,
+    relayout: bool,
+
+    instances: Vec<VoxelInstance>,
+    // Instances as of the last `update_buffers`, changes made through
+    // `instance_mut` are found by comparing with them.
+    uploaded_instances: Vec<VoxelInstance>,
+
+    node_buffer: Buffer,
+    instance_buffer: Buffer,
+    tlas_buffer: Buffer,
+    space_uniform_buffer: Buffer,
+
+    uniform_bind_group_layout: BindGroupLayout,
+    uniform_bind_group: Option<BindGroup>,
+    binding: u32,
+}
+
+impl VoxelSpace {
+    pub const NODE_SIZE: u64 = CompiledUniform::WGSL_SIZE as u64;
+    const INSTANCE_SIZE: u64 = VoxelInstanceUniform::WGSL_SIZE as u64;
+    const TLAS_NODE_SIZE: u64 = TlasNodeUniform::WGSL_SIZE as u64;
+
+    pub fn new(binding: u32, app: &App) -> Self {
+        let node_buffer = Self::create_buffer("Voxel space nodes (buffer)", Self::NODE_SIZE, app);
+        let instance_buffer = Self::create_buffer("Voxel space instances (buffer)", Self::INSTANCE_SIZE, app);
+        let tlas_buffer = Self::create_buffer("Voxel space tlas (buffer)", Self::TLAS_NODE_SIZE, app);
+
+        let space_uniform_buffer = app.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Voxel space uniform (buffer)"),
+            contents: &VoxelSpaceUniform { instance_count: 0, tlas_node_count: 0 }.to_wgsl_bytes(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+        });
+
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None
+        };
+
+        let uniform_bind_group_layout = app.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                storage_entry(binding),
+                BindGroupLayoutEntry {
+                    binding: binding + 1,
+                    visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                storage_entry(binding + 2),
+                storage_entry(binding + 3),
+            ],
+            label: Some("Voxel space bind group layout")
+        });
+
+        Self {
+            trees: vec![],
+            slots: vec![],
+            relayout: true,
+
+            instances: vec![],
+            uploaded_instances: vec![],
+
+            node_buffer,
+            instance_buffer,
+            tlas_buffer,
+            space_uniform_buffer,
+
+            uniform_bind_group_layout,
+            uniform_bind_group: None,
+            binding,
+        }
+    }
+
+    pub fn uniform_bind_group_layout(&self) -> &BindGroupLayout {
+        &self.uniform_bind_group_layout
+    }
+
+    pub fn uniform_bind_group(&self) -> &BindGroup {
+        self.uniform_bind_group.as_ref().unwrap()
+    }
+
+    pub fn init(&mut self, binding: u32, app: &App) -> Result<(), SceneError> {
+        self.binding = binding;
+        self.create_bind_group(app);
+
+        self.update_buffers(app)
+    }
+
+    fn create_buffer(label: &str, size: u64, app: &App) -> Buffer {
+        app.device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        })
+    }
+
+    fn create_bind_group(&mut self, app: &App) {
+        let uniform_bind_group = app.device.create_bind_group(&BindGroupDescriptor {
+            layout: &self.uniform_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: self.binding,
+                    resource: self.node_buffer.as_entire_binding()
+                },
+                BindGroupEntry {
+                    binding: self.binding + 1,
+                    resource: self.space_uniform_buffer.as_entire_binding()
+                },
+                BindGroupEntry {
+                    binding: self.binding + 2,
+                    resource: self.instance_buffer.as_entire_binding()
+                },
+                BindGroupEntry {
+                    binding: self.binding + 3,
+                    resource: self.tlas_buffer.as_entire_binding()
+                }
+            ],
+            label: Some("Voxel space bind group")
+        });
+
+        self.uniform_bind_group = Some(uniform_bind_group);
+    }
+
+    // Also adds a `VoxelInstance::new` of the tree, so it shows up at its own
+    // transform. Remove that instance to only place the tree with others.
+    pub fn add_tree(&mut self, tree: VoxelTree) -> usize {
+        self.trees.push(tree);
+        self.relayout = true;
+
+        let index = self.trees.len() - 1;
+
+        self.add_instance(VoxelInstance::new(index));
+
+        index
+    }
+
+    // Instances of the tree are removed with it, trees after `index` move one
+    // place down.
+    pub fn remove_tree(&mut self, index: usize) -> VoxelTree {
+        self.relayout = true;
+
+        self.instances.retain(|instance| instance.tree != index);
+
+        for instance in &mut self.instances {
+            if instance.tree > index {
+                instance.tree -= 1;
+            }
+        }
+
+        self.trees.remove(index)
+    }
+
+    pub fn tree(&self, index: usize) -> Option<&VoxelTree> {
+        self.trees.get(index)
+    }
+
+    pub fn tree_mut(&mut self, index: usize) -> Option<&mut VoxelTree> {
+        self.trees.get_mut(index)
+    }
+
+    pub fn trees(&self) -> &[VoxelTree] {
+        &self.trees
+    }
+
+    // Panics for an unknown tree or a scale that is not positive, rays would
+    // be divided by it.
+    pub fn add_instance(&mut self, instance: VoxelInstance) -> usize {
+        assert!(instance.tree < self.trees.len(), "instance of unknown tree {}", instance.tree);
+        assert!(is_valid_scale(instance.scale), "instance scale {} is not positive", instance.scale);
+
+        self.instances.push(instance);
+
+        self.instances.len() - 1
+    }
+
+    // Instances after `index` move one place down.
+    pub fn remove_instance(&mut self, index: usize) -> VoxelInstance {
+        self.instances.remove(index)
+    }
+
+    pub fn instance(&self, index: usize) -> Option<&VoxelInstance> {
+        self.instances.get(index)
+    }
+
+    pub fn instance_mut(&mut self, index: usize) -> Option<&mut VoxelInstance> {
+        self.instances.get_mut(index)
+    }
+
+    pub fn instances(&self) -> &[VoxelInstance] {
+        &self.instances
+    }
+
+    // Closest hit over every instance together with the index of the instance.
+    pub fn raycast(&self, origin: [f32; 3], dir: [f32; 3]) -> Option<(usize, Hit)> {
+        raycast_instances(&self.trees, &self.instances, origin, dir)
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.relayout || self.instances != self.uploaded_instances || self.trees.iter().any(VoxelTree::is_dirty)
+    }
+
+    // Every tree owns a slot of the shared node buffer with some room to grow,
+    // so an edit that adds nodes rarely moves the other trees. Returns true if
+    // the slots changed and all nodes have to be uploaded again.
+    fn layout(&mut self) -> bool {
+        let fits = self.slots.len() == self.trees.len() && self.trees.iter()
+            .zip(&self.slots)
+            .all(|(tree, slot)| tree.nodes().len() <= slot.len());
+
+        if fits && !self.relayout {
+            return false;
+        }
+
+        let mut start = 0;
+
+        self.slots = self.trees.iter()
+            .map(|tree| {
+                let len = tree.nodes().len();
+                let slot = start..start + len + len / 2;
+
+                start = slot.end;
+                slot
+            })
+            .collect();
+
+        self.relayout = false;
+
+        true
+    }
+
+    // Returns a bigger buffer if `size` bytes do not fit into `buffer`.
+    fn grow(buffer: &Buffer, label: &str, size: u64, stride: u64, app: &App) -> Result<Option<Buffer>, SceneError> {
+        let max = app.device.limits().max_storage_buffer_binding_size as u64;
+
+        if size > max {
+            return Err(SceneError::TooLarge { nodes: (size / stride) as usize, size, max });
+        }
+
+        if size <= buffer.size() {
+            return Ok(None);
+        }
+
+        let size = (buffer.size() * 2).clamp(size, max / stride * stride);
+
+        debug!("{} resized to {} bytes", label, size);
+
+        Ok(Some(Self::create_buffer(label, size, app)))
+    }
+
+    // Uploads the nodes changed since the last call and rebuilds the instance
+    // list together with the top level BVH over the instance bounds.
+    pub fn update_buffers(&mut self, app: &App) -> Result<(), SceneError> {
+        if !self.is_dirty() {
+            return Ok(());
+        }
+
+        // `cast_ray_tree` would silently drop the nodes that don't fit its stack.
+        for (index, tree) in self.trees.iter().enumerate().filter(|(_, tree)| tree.is_dirty()) {
+            let depth = tree.depth();
+
+            if depth > MAX_TREE_DEPTH {
+                return Err(SceneError::TooDeep { tree: index, depth, max: MAX_TREE_DEPTH });
+            }
+        }
+
+        // `instance_mut` may have changed it since `add_instance`.
+        if let Some((index, instance)) = self.instances.iter().enumerate().find(|(_, instance)| !is_valid_scale(instance.scale)) {
+            return Err(SceneError::InvalidScale { instance: index, scale: instance.scale });
+        }
+
+        let mut full_update = self.layout();
+        let mut rebind = false;
+
+        let node_count = self.slots.last().map_or(0, |slot| slot.end).max(1) as u64;
+
+        if let Some(buffer) = Self::grow(&self.node_buffer, "Voxel space nodes (buffer)", node_count * Self::NODE_SIZE, Self::NODE_SIZE, app)? {
+            self.node_buffer = buffer;
+
+            full_update = true;
+            rebind = true;
+        }
+
+        for (tree, slot) in self.trees.iter_mut().zip(&self.slots) {
+            let changes = tree.take_changes();
+
+            if full_update {
+                match tree.wgsl_bytes() {
+                    Some(bytes) => app.queue.write_buffer(&self.node_buffer, slot.start as u64 * Self::NODE_SIZE, bytes),
+                    None => write_nodes(&self.node_buffer, slot.start, tree.nodes(), app),
+                }
+            } else {
+                for range in changes {
+                    write_nodes(&self.node_buffer, slot.start + range.start, &tree.nodes()[range], app);
+                }
+            }
+        }
+
+        let mut instances = vec![];
+        let mut bounds = vec![];
+
+        for instance in &self.instances {
+            let tree = &self.trees[instance.tree];
+            let slot = &self.slots[instance.tree];
+
+            if let Some(instance_bounds) = instance.bounds(tree) {
+                bounds.push((instances.len() as u32, instance_bounds));
+            }
+
+            let (rotation, position, scale) = instance.world_to_tree(tree);
+
+            instances.push(VoxelInstanceUniform {
+                rotation,
+                position,
+                scale,
+                root: tree.root() as u32,
+                node_offset: slot.start as u32,
+                node_count: tree.nodes().len() as u32
+            });
+        }
+
+        let tlas = tlas::build(&bounds);
+
+        let instance_size = instances.len().max(1) as u64 * Self::INSTANCE_SIZE;
+
+        if let Some(buffer) = Self::grow(&self.instance_buffer, "Voxel space instances (buffer)", instance_size, Self::INSTANCE_SIZE, app)? {
+            self.instance_buffer = buffer;
+            rebind = true;
+        }
+
+        let tlas_size = tlas.len().max(1) as u64 * Self::TLAS_NODE_SIZE;
+
+        if let Some(buffer) = Self::grow(&self.tlas_buffer, "Voxel space tlas (buffer)", tlas_size, Self::TLAS_NODE_SIZE, app)? {
+            self.tlas_buffer = buffer;
+            rebind = true;
+        }
+
+        let mut instance_bytes = Vec::with_capacity(instances.len() * Self::INSTANCE_SIZE as usize);
+        let mut tlas_bytes = Vec::with_capacity(tlas.len() * Self::TLAS_NODE_SIZE as usize);
+
+        for instance in &instances {
+            instance.write_wgsl_bytes(&mut instance_bytes);
+        }
+
+        for node in &tlas {
+            node.write_wgsl_bytes(&mut tlas_bytes);
+        }
+
+        app.queue.write_buffer(&self.instance_buffer, 0, &instance_bytes);
+        app.queue.write_buffer(&self.tlas_buffer, 0, &tlas_bytes);
+
+        app.queue.write_buffer(&self.space_uniform_buffer, 0, &VoxelSpaceUniform {
+            instance_count: instances.len() as u32,
+            tlas_node_count: tlas.len() as u32
+        }.to_wgsl_bytes());
+
+        if rebind && self.uniform_bind_group.is_some() {
+            self.create_bind_group(app);
+        }
+
+        self.uploaded_instances.clone_from(&self.instances);
+
+        Ok(())
+    }
+}
+
+// Rays are divided by the scale.
+fn is_valid_scale(scale: f32) -> bool {
+    scale > 0.0 && scale.is_finite()
+}
+
+pub(crate) fn raycast_instances(trees: &[VoxelTree], instances: &[VoxelInstance], origin: [f32; 3], dir: [f32; 3]) -> Option<(usize, Hit)> {
+    instances.iter()
+        .enumerate()
+        .filter_map(|(index, instance)| {
+            Some((index, instance.raycast(&trees[instance.tree], origin, dir)?))
+        })
+        .min_by(|a, b| a.1.fraction.total_cmp(&b.1.fraction))
+}
+
+fn write_nodes(buffer: &Buffer, first: usize, nodes: &[CompiledUniform], app: &App) {
+    if nodes.is_empty() {
+        return;
+    }
+
+    let mut out = Vec::<u8>::with_capacity(nodes.len() * VoxelSpace::NODE_SIZE as usize);
+
+    for exp in nodes {
+        exp.write_wgsl_bytes(&mut out);
+    }
+
+    app.queue.write_buffer(buffer, first as u64 * VoxelSpace::NODE_SIZE, out.as_slice());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::{MaterialUniform, SvoBuilder};
+
+    fn tree(depth: u32, voxels: &[[u32; 3]]) -> VoxelTree {
+        let mut builder = SvoBuilder::new(depth);
+
+        for [x, y, z] in voxels {
+            builder.set_voxel(*x, *y, *z, MaterialUniform::default());
+        }
+
+        let nodes = builder.build();
+        let root = nodes.len() - 1;
+
+        let mut tree = VoxelTree::new();
+        tree.set_nodes(nodes, root);
+        tree
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!((0..3).all(|axis| (a[axis] - b[axis]).abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn instance_ray_is_moved_and_scaled() {
+        let tree = tree(0, &[[0, 0, 0]]);
+
+        let instance = VoxelInstance {
+            position: [10.0, 0.0, 0.0],
+            scale: 2.0,
+            ..VoxelInstance::new(0)
+        };
+
+        // The unit voxel spans [9, 11] on x after the transform.
+        let hit = instance.raycast(&tree, [0.0, 0.5, 0.0], [1.0, 0.0, 0.0]).unwrap();
+
+        assert!((hit.fraction - 9.0).abs() < 1e-5, "hit at {}", hit.fraction);
+        assert_close(hit.normal, [-1.0, 0.0, 0.0]);
+
+        assert_eq!(instance.raycast(&tree, [0.0, 1.5, 0.0], [1.0, 0.0, 0.0]), None);
+    }
+
+    #[test]
+    fn instance_ray_is_rotated() {
+        // Voxel (1, 0, 0) spans x in [0, 1], y and z in [-1, 0] in the tree.
+        let tree = tree(1, &[[1, 0, 0]]);
+
+        // 90 degrees around z, world (x, y) is (y, -x) in the tree, so the
+        // voxel is at x in [0, 1], y in [0, 1] in the world.
+        let instance = VoxelInstance {
+            rotation: [
+                [0.0, 1.0, 0.0],
+                [-1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            ..VoxelInstance::new(0)
+        };
+
+        let hit = instance.raycast(&tree, [0.5, 0.5, -10.0], [0.0, 0.0, 1.0]).unwrap();
+
+        assert!((hit.fraction - 9.0).abs() < 1e-5, "hit at {}", hit.fraction);
+        assert_close(hit.normal, [0.0, 0.0, -1.0]);
+
+        // Where the voxel would be without the rotation.
+        assert_eq!(instance.raycast(&tree, [0.5, -0.5, -10.0], [0.0, 0.0, 1.0]), None);
+
+        // The normal comes back in world space.
+        let hit = instance.raycast(&tree, [-10.0, 0.5, -0.5], [1.0, 0.0, 0.0]).unwrap();
+
+        assert!((hit.fraction - 10.0).abs() < 1e-5, "hit at {}", hit.fraction);
+        assert_close(hit.normal, [-1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn instance_is_placed_relative_to_tree_transform() {
+        let mut tree = tree(0, &[[0, 0, 0]]);
+        tree.set_transform([0.0, 0.0, 3.0], CompiledUniform::IDENTITY);
+
+        let instance = VoxelInstance {
+            position: [10.0, 0.0, 0.0],
+            scale: 2.0,
+            ..VoxelInstance::new(0)
+        };
+
+        // The unit voxel is moved to z in [2.5, 3.5] by the tree, then scaled
+        // and moved by the instance.
+        let bounds = instance.bounds(&tree).unwrap();
+
+        assert_close(bounds.min, [9.0, -1.0, 5.0]);
+        assert_close(bounds.max, [11.0, 1.0, 7.0]);
+
+        let hit = instance.raycast(&tree, [0.0, 0.0, 6.0], [1.0, 0.0, 0.0]).unwrap();
+
+        assert!((hit.fraction - 9.0).abs() < 1e-5, "hit at {}", hit.fraction);
+        assert_eq!(instance.raycast(&tree, [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]), None);
+
+        // An untouched instance leaves the tree at its own transform.
+        let hit = VoxelInstance::new(0).raycast(&tree, [0.0, 0.0, -10.0], [0.0, 0.0, 1.0]).unwrap();
+
+        assert!((hit.fraction - 12.5).abs() < 1e-5, "hit at {}", hit.fraction);
+    }
+
+    #[test]
+    fn space_ray_picks_closest_instance() {
+        let trees = [tree(0, &[[0, 0, 0]])];
+
+        let at = |x: f32, y: f32| VoxelInstance {
+            position: [x, y, 0.0],
+            ..VoxelInstance::new(0)
+        };
+
+        // The far instance comes first, so the order of the list doesn't decide.
+        let instances = [at(10.0, 0.0), at(5.0, 0.0), at(20.0, 0.0)];
+
+        let (index, hit) = raycast_instances(&trees, &instances, [0.0; 3], [1.0, 0.0, 0.0]).unwrap();
+
+        assert_eq!(index, 1);
+        assert!((hit.fraction - 4.5).abs() < 1e-5, "hit at {}", hit.fraction);
+
+        // A ray that passes next to the near instance hits the one behind it.
+        let instances = [at(5.0, 1.0), at(10.0, 0.3)];
+
+        let (index, hit) = raycast_instances(&trees, &instances, [0.0; 3], [1.0, 0.0, 0.0]).unwrap();
+
+        assert_eq!(index, 1);
+        assert!((hit.fraction - 9.5).abs() < 1e-5, "hit at {}", hit.fraction);
+
+        assert_eq!(raycast_instances(&trees, &instances, [0.0; 3], [-1.0, 0.0, 0.0]), None);
+    }
+}
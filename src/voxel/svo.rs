@@ -0,0 +1,298 @@
+use std::{fs::File, io::{self, Read, Write}, mem};
+
+use bytemuck::{Zeroable, Pod, bytes_of, pod_read_unaligned};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use super::CompiledUniform;
+
+pub const SVO_MAGIC: [u8; 4] = *b"FVSO";
+pub const SVO_VERSION: u32 = 2;
+
+pub const SVO_FLAG_COMPRESSED: u32 = 1 << 0;
+
+// The node table is stored exactly like the `Cube` array of the voxel storage
+// buffer, `node_stride` is `CompiledUniform::WGSL_SIZE` of the writer. An
+// uncompressed file is mapped and its table uploaded without conversion.
+//
+// Materials are stored inline in every node for the same reason, a separate
+// material table would have to be resolved into the nodes on load.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[derive(Zeroable, Pod)]
+pub struct SvoHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub flags: u32,
+    pub root: u32,
+    pub node_count: u32,
+    pub node_stride: u32,
+    pub data_size: u32,
+    pub _offset: u32,
+}
+
+enum SvoData {
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+
+pub struct SvoFile {
+    header: SvoHeader,
+    data: SvoData,
+}
+
+impl SvoFile {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(file: String) -> io::Result<Self> {
+        let file = File::open(file)?;
+
+        // SAFETY: the mapping is read-only and the file is expected not to be
+        // truncated while the scene is loaded.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let header = read_header(&map)?;
+
+        if header.flags & SVO_FLAG_COMPRESSED != 0 {
+            return Self::from_bytes(&map);
+        }
+
+        if header.data_size as usize != table_size(&header) {
+            return Err(invalid("node table does not match header"));
+        }
+
+        let out = Self {
+            header,
+            data: SvoData::Mapped(map),
+        };
+
+        out.validate()?;
+
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let header = read_header(bytes)?;
+        let data = &bytes[mem::size_of::<SvoHeader>()..];
+
+        let table = if header.flags & SVO_FLAG_COMPRESSED != 0 {
+            let mut table = Vec::new();
+
+            // Stops one byte after the table size from the header, so a longer
+            // stream is rejected without inflating all of it.
+            ZlibDecoder::new(data)
+                .take(table_size(&header) as u64 + 1)
+                .read_to_end(&mut table)?;
+
+            table
+        } else {
+            data.to_vec()
+        };
+
+        if table.len() != table_size(&header) {
+            return Err(invalid("node table does not match header"));
+        }
+
+        let out = Self {
+            header,
+            data: SvoData::Owned(table),
+        };
+
+        out.validate()?;
+
+        Ok(out)
+    }
+
+    pub fn header(&self) -> &SvoHeader {
+        &self.header
+    }
+
+    pub fn root(&self) -> u32 {
+        self.header.root
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.header.node_count as usize
+    }
+
+    // The node table in the layout of the voxel storage buffer.
+    pub fn node_bytes(&self) -> &[u8] {
+        match &self.data {
+            #[cfg(not(target_arch = "wasm32"))]
+            SvoData::Mapped(map) => &map[mem::size_of::<SvoHeader>()..],
+            SvoData::Owned(table) => table,
+        }
+    }
+
+    pub fn node(&self, index: usize) -> CompiledUniform {
+        CompiledUniform::from_wgsl_bytes(&self.node_bytes()[index * CompiledUniform::WGSL_SIZE..])
+    }
+
+    pub fn compile(&self) -> Vec<CompiledUniform> {
+        (0..self.node_count())
+            .map(|index| self.node(index))
+            .collect()
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let node_count = self.node_count();
+
+        if self.header.root as usize >= node_count && node_count != 0 {
+            return Err(invalid(format!("root {} is out of range", self.header.root)));
+        }
+
+        for index in 0..node_count {
+            let node = self.node(index);
+
+            if node.is_leaf < 1.0 && node.childs.iter().any(|child| *child as usize >= node_count) {
+                return Err(invalid(format!("node {}: child is out of range", index)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn write_svo(file: String, nodes: &[CompiledUniform], root: u32, compress: bool) -> io::Result<()> {
+    let mut table = Vec::<u8>::with_capacity(nodes.len() * CompiledUniform::WGSL_SIZE);
+
+    for node in nodes {
+        node.write_wgsl_bytes(&mut table);
+    }
+
+    if compress {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&table)?;
+
+        table = encoder.finish()?;
+    }
+
+    let header = SvoHeader {
+        magic: SVO_MAGIC,
+        version: SVO_VERSION,
+        flags: if compress { SVO_FLAG_COMPRESSED } else { 0 },
+        root,
+        node_count: nodes.len() as u32,
+        node_stride: CompiledUniform::WGSL_SIZE as u32,
+        data_size: table.len() as u32,
+        _offset: 0,
+    };
+
+    let mut file = File::create(file)?;
+
+    file.write_all(bytes_of(&header))?;
+    file.write_all(&table)
+}
+
+fn read_header(bytes: &[u8]) -> io::Result<SvoHeader> {
+    let header: SvoHeader = bytes.get(..mem::size_of::<SvoHeader>())
+        .map(pod_read_unaligned)
+        .ok_or_else(|| invalid("file is too small for svo header"))?;
+
+    if header.magic != SVO_MAGIC {
+        return Err(invalid("missing svo header"));
+    }
+
+    if header.version != SVO_VERSION {
+        return Err(invalid(format!("unsupported svo version {}", header.version)));
+    }
+
+    if header.node_stride as usize != CompiledUniform::WGSL_SIZE {
+        return Err(invalid(format!("svo nodes are {} bytes, the shader expects {}", header.node_stride, CompiledUniform::WGSL_SIZE)));
+    }
+
+    if bytes.len() - mem::size_of::<SvoHeader>() != header.data_size as usize {
+        return Err(invalid("svo data size does not match header"));
+    }
+
+    Ok(header)
+}
+
+fn table_size(header: &SvoHeader) -> usize {
+    header.node_count as usize * CompiledUniform::WGSL_SIZE
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::{MaterialUniform, SvoBuilder};
+
+    fn nodes() -> Vec<CompiledUniform> {
+        let mut builder = SvoBuilder::new(3);
+
+        builder.set_voxel(1, 2, 3, MaterialUniform { roughness: 0.5, ..MaterialUniform::default() });
+        builder.set_voxel(7, 0, 4, MaterialUniform { emmitance: [2.0, 0.0, 1.0], opacity: 0.25, ..MaterialUniform::default() });
+
+        let mut nodes = builder.build();
+        nodes[0].rotation = [[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+        nodes
+    }
+
+    fn wgsl_bytes(nodes: &[CompiledUniform]) -> Vec<u8> {
+        let mut out = vec![];
+
+        for node in nodes {
+            node.write_wgsl_bytes(&mut out);
+        }
+
+        out
+    }
+
+    #[test]
+    fn node_table_is_storage_buffer_layout() {
+        let nodes = nodes();
+
+        for compress in [false, true] {
+            let path = std::env::temp_dir().join(format!("fast-voxel-svo-test-{}-{}.svo", std::process::id(), compress));
+
+            write_svo(path.display().to_string(), &nodes, nodes.len() as u32 - 1, compress).unwrap();
+
+            let svo = SvoFile::open(path.display().to_string()).unwrap();
+            let _ = std::fs::remove_file(&path);
+
+            assert_eq!(svo.root() as usize, nodes.len() - 1);
+            assert_eq!(svo.node_bytes(), wgsl_bytes(&nodes).as_slice());
+            assert_eq!(wgsl_bytes(&svo.compile()), wgsl_bytes(&nodes));
+        }
+    }
+
+    #[test]
+    fn stops_inflating_after_node_table() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&vec![0; 16 << 20]).unwrap();
+
+        let data = encoder.finish().unwrap();
+
+        let header = SvoHeader {
+            magic: SVO_MAGIC,
+            version: SVO_VERSION,
+            flags: SVO_FLAG_COMPRESSED,
+            root: 0,
+            node_count: 1,
+            node_stride: CompiledUniform::WGSL_SIZE as u32,
+            data_size: data.len() as u32,
+            _offset: 0,
+        };
+
+        let bytes = [bytes_of(&header), &data].concat();
+
+        assert!(SvoFile::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_other_node_stride() {
+        let nodes = nodes();
+        let path = std::env::temp_dir().join(format!("fast-voxel-svo-test-{}-stride.svo", std::process::id()));
+
+        write_svo(path.display().to_string(), &nodes, 0, false).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        bytes[20..24].copy_from_slice(&128u32.to_le_bytes());
+
+        assert!(SvoFile::from_bytes(&bytes).is_err());
+    }
+}
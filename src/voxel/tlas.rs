@@ -0,0 +1,109 @@
+use bytemuck::Zeroable;
+
+pub use crate::uniforms::TlasNodeUniform;
+
+pub const TLAS_NO_OBJECT: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    pub fn center(&self) -> [f32; 3] {
+        [0, 1, 2].map(|axis| (self.min[axis] + self.max[axis]) / 2.0)
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: [0, 1, 2].map(|axis| self.min[axis].min(other.min[axis])),
+            max: [0, 1, 2].map(|axis| self.max[axis].max(other.max[axis])),
+        }
+    }
+
+    pub fn scale(&self, scale: f32) -> Aabb {
+        Aabb {
+            min: self.min.map(|x| x * scale),
+            max: self.max.map(|x| x * scale),
+        }
+    }
+
+    // Bounds of the box after moving every point `p` to `position + rotation^T * p`,
+    // the inverse of the world to local transform used by the shader.
+    pub fn transform(&self, rotation: [[f32; 3]; 3], position: [f32; 3]) -> Aabb {
+        let center = self.center();
+        let extent = [0, 1, 2].map(|axis| (self.max[axis] - self.min[axis]) / 2.0);
+
+        let center = [0, 1, 2].map(|i| {
+            position[i] + (0..3).map(|k| rotation[k][i] * center[k]).sum::<f32>()
+        });
+
+        let extent = [0, 1, 2].map(|i| {
+            (0..3).map(|k| rotation[k][i].abs() * extent[k]).sum::<f32>()
+        });
+
+        Aabb {
+            min: [0, 1, 2].map(|axis| center[axis] - extent[axis]),
+            max: [0, 1, 2].map(|axis| center[axis] + extent[axis]),
+        }
+    }
+}
+
+// Builds the top level BVH over object bounds by splitting on the longest axis
+// at the median. The root is the first node, an empty input gives no nodes.
+pub fn build(objects: &[(u32, Aabb)]) -> Vec<TlasNodeUniform> {
+    let mut nodes = vec![];
+
+    if objects.is_empty() {
+        return nodes;
+    }
+
+    let mut objects = objects.to_vec();
+
+    nodes.push(TlasNodeUniform::zeroed());
+    build_node(&mut objects, 0, &mut nodes);
+
+    nodes
+}
+
+fn build_node(objects: &mut [(u32, Aabb)], index: usize, nodes: &mut Vec<TlasNodeUniform>) {
+    let bounds = objects[1..].iter()
+        .fold(objects[0].1, |bounds, (_, object)| bounds.union(object));
+
+    if let [(object, _)] = objects {
+        nodes[index] = TlasNodeUniform {
+            min: bounds.min,
+            left: 0,
+            max: bounds.max,
+            object: *object,
+        };
+
+        return;
+    }
+
+    let axis = (0..3)
+        .max_by(|a, b| {
+            (bounds.max[*a] - bounds.min[*a]).total_cmp(&(bounds.max[*b] - bounds.min[*b]))
+        })
+        .unwrap();
+
+    objects.sort_by(|(_, a), (_, b)| a.center()[axis].total_cmp(&b.center()[axis]));
+
+    let left = nodes.len();
+
+    nodes.push(TlasNodeUniform::zeroed());
+    nodes.push(TlasNodeUniform::zeroed());
+
+    nodes[index] = TlasNodeUniform {
+        min: bounds.min,
+        left: left as u32,
+        max: bounds.max,
+        object: TLAS_NO_OBJECT,
+    };
+
+    let (first, second) = objects.split_at_mut(objects.len() / 2);
+
+    build_node(first, left, nodes);
+    build_node(second, left + 1, nodes);
+}
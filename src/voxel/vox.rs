@@ -0,0 +1,300 @@
+use std::{collections::HashMap, fs, io};
+
+use super::{CompiledUniform, MaterialUniform, SvoBuilder};
+
+pub struct VoxModel {
+    pub size: [u32; 3],
+    pub voxels: Vec<[u8; 4]>,
+}
+
+pub struct VoxFile {
+    pub version: u32,
+    pub models: Vec<VoxModel>,
+    pub palette: [[u8; 4]; 256],
+    pub materials: HashMap<u32, HashMap<String, String>>,
+}
+
+impl VoxFile {
+    pub fn open(file: String) -> io::Result<Self> {
+        Self::parse(&fs::read(file)?)
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { data, pos: 0 };
+
+        if reader.read_bytes(4)? != b"VOX " {
+            return Err(invalid("missing VOX header"));
+        }
+
+        let version = reader.read_u32()?;
+
+        let (id, content_size, children_size) = reader.read_chunk_header()?;
+
+        if id != *b"MAIN" {
+            return Err(invalid("first chunk is not MAIN"));
+        }
+
+        reader.read_bytes(content_size)?;
+
+        let end = reader.pos + children_size;
+
+        let mut out = Self {
+            version,
+            models: vec![],
+            palette: default_palette(),
+            materials: HashMap::new(),
+        };
+
+        let mut size = None;
+
+        while reader.pos < end {
+            let (id, content_size, children_size) = reader.read_chunk_header()?;
+            let mut chunk = Reader { data: reader.read_bytes(content_size)?, pos: 0 };
+
+            reader.read_bytes(children_size)?;
+
+            match &id {
+                b"SIZE" => {
+                    size = Some([chunk.read_u32()?, chunk.read_u32()?, chunk.read_u32()?]);
+                },
+
+                b"XYZI" => {
+                    let size = size.take()
+                        .ok_or_else(|| invalid("XYZI chunk without SIZE chunk"))?;
+
+                    let count = chunk.read_u32()? as usize;
+
+                    if count > chunk.remaining() / 4 {
+                        return Err(invalid(format!("XYZI chunk has {} voxels, but only room for {}", count, chunk.remaining() / 4)));
+                    }
+
+                    let mut voxels = Vec::with_capacity(count);
+
+                    for _ in 0..count {
+                        let voxel = chunk.read_bytes(4)?;
+
+                        voxels.push([voxel[0], voxel[1], voxel[2], voxel[3]]);
+                    }
+
+                    out.models.push(VoxModel { size, voxels });
+                },
+
+                b"RGBA" => {
+                    for i in 0..255 {
+                        let color = chunk.read_bytes(4)?;
+
+                        out.palette[i + 1] = [color[0], color[1], color[2], color[3]];
+                    }
+                },
+
+                b"MATL" => {
+                    let id = chunk.read_u32()?;
+                    let properties = chunk.read_dict()?;
+
+                    out.materials.insert(id, properties);
+                },
+
+                _ => {}
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn material(&self, color_index: u8) -> MaterialUniform {
+        let color = self.palette[color_index as usize];
+        let reflectance = [
+            color[0] as f32 / 255.0,
+            color[1] as f32 / 255.0,
+            color[2] as f32 / 255.0,
+        ];
+
+        let emit = self.materials.get(&(color_index as u32))
+            .filter(|properties| properties.get("_type").map(String::as_str) == Some("_emit"))
+            .and_then(|properties| properties.get("_emit"))
+            .and_then(|value| value.parse::<f32>().ok())
+            .unwrap_or(0.0);
+
+        MaterialUniform {
+            emmitance: reflectance.map(|x| x * emit),
+            reflectance,
+            roughness: 0.0,
+            opacity: 0.0,
+        }
+    }
+
+    pub fn compile(&self, model: usize) -> io::Result<Vec<CompiledUniform>> {
+        let model = self.models.get(model)
+            .ok_or_else(|| invalid(format!("model {} not found", model)))?;
+
+        let size = model.size.into_iter().max().unwrap_or(1).max(1).next_power_of_two();
+        let mut builder = SvoBuilder::new(size.trailing_zeros());
+
+        for voxel in &model.voxels {
+            let [x, y, z, color_index] = voxel.map(|x| x as u32);
+
+            if x >= model.size[0] || y >= model.size[1] || z >= model.size[2] {
+                return Err(invalid(format!("voxel ({}, {}, {}) is outside of model", x, y, z)));
+            }
+
+            builder.set_voxel(x, y, z, self.material(color_index as u8));
+        }
+
+        Ok(builder.build())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn read_bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + count)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+
+        self.pos += count;
+
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        let len = self.read_u32()? as usize;
+
+        String::from_utf8(self.read_bytes(len)?.to_vec())
+            .map_err(|_| invalid("string is not valid utf-8"))
+    }
+
+    fn read_dict(&mut self) -> io::Result<HashMap<String, String>> {
+        let count = self.read_u32()?;
+        let mut dict = HashMap::new();
+
+        for _ in 0..count {
+            let key = self.read_string()?;
+            let value = self.read_string()?;
+
+            dict.insert(key, value);
+        }
+
+        Ok(dict)
+    }
+
+    fn read_chunk_header(&mut self) -> io::Result<([u8; 4], usize, usize)> {
+        let id = self.read_bytes(4)?;
+
+        Ok((
+            [id[0], id[1], id[2], id[3]],
+            self.read_u32()? as usize,
+            self.read_u32()? as usize,
+        ))
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// MagicaVoxel falls back to this palette when the file has no RGBA chunk.
+fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0; 4]; 256];
+    let mut i = 1;
+
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                if r == 0 && g == 0 && b == 0 {
+                    continue;
+                }
+
+                palette[i] = [r, g, b, 0xff];
+                i += 1;
+            }
+        }
+    }
+
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    for mask in [[1, 0, 0], [0, 1, 0], [0, 0, 1], [1, 1, 1]] {
+        for value in ramp {
+            palette[i] = [mask[0] * value, mask[1] * value, mask[2] * value, 0xff];
+            i += 1;
+        }
+    }
+
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+
+        out.extend((content.len() as u32).to_le_bytes());
+        out.extend((children.len() as u32).to_le_bytes());
+        out.extend(content);
+        out.extend(children);
+
+        out
+    }
+
+    fn vox(children: &[u8]) -> Vec<u8> {
+        let mut out = b"VOX ".to_vec();
+
+        out.extend(150u32.to_le_bytes());
+        out.extend(chunk(b"MAIN", &[], children));
+
+        out
+    }
+
+    #[test]
+    fn parses_test_vox() {
+        let file = VoxFile::parse(include_bytes!("../../test.vox")).unwrap();
+
+        assert_eq!(file.version, 200);
+        assert_eq!(file.models.len(), 1);
+        assert_eq!(file.models[0].size, [2, 2, 2]);
+        assert_eq!(file.models[0].voxels.len(), 7);
+        assert_eq!(file.models[0].voxels[0], [0, 0, 0, 79]);
+        assert!(!file.materials.is_empty());
+
+        let nodes = file.compile(0).unwrap();
+
+        assert_eq!(nodes.len(), 9);
+    }
+
+    #[test]
+    fn rejects_voxel_count_beyond_chunk() {
+        let mut xyzi = u32::MAX.to_le_bytes().to_vec();
+
+        xyzi.extend([0, 0, 0, 1]);
+
+        let size = [1u32, 1, 1].iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+        let data = vox(&[chunk(b"SIZE", &size, &[]), chunk(b"XYZI", &xyzi, &[])].concat());
+
+        let error = VoxFile::parse(&data).err().unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let data = include_bytes!("../../test.vox");
+
+        assert!(VoxFile::parse(&data[..100]).is_err());
+    }
+}
@@ -1,33 +1,565 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::token::In;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Expr, Fields, Lit, LitStr, Type};
 
-impl Into<Vec<u8>> for u32 {
-    fn into(self) -> Vec<u8> {
-        bytemuck::cast_slice(&[self]).to_owned()
+// Lays a struct out with the WGSL rules for host shareable memory and emits on
+// an inherent impl:
+//
+// - `WGSL_NAME`, `WGSL_ALIGN`, `WGSL_SIZE` and a `WGSL_OFFSET_<FIELD>` per field
+// - `WGSL_MEMBERS`, the WGSL name, offset and size of every member in order
+// - `WGSL_UNIFORM_LAYOUT`, true if the layout also follows the uniform buffer
+//   rules, with or without `#[wgsl(uniform)]`
+// - `wgsl_struct()`, the matching WGSL declaration
+// - `write_wgsl_bytes()` and `to_wgsl_bytes()`, the padded bytes of a value
+// - `from_wgsl_bytes()`, the value back from those bytes, skipped fields are
+//   `Default::default()`
+//
+// `f32`, `u32` and `i32` are scalars, `[T; 2..=4]` of a scalar is a vector,
+// `[[f32; R]; C]` with C and R in 2..=4 is a `matCxR` stored by columns, any
+// other array is a WGSL array and every other type has to derive this too.
+//
+// Attributes:
+// - `#[wgsl(name = "...")]` renames the struct or a field on the WGSL side
+// - `#[wgsl(skip)]` leaves a field out, for `_offset` style padding
+// - `#[wgsl(transpose)]` marks a matrix stored by rows, `[[f32; C]; R]`
+// - `#[wgsl(uniform)]` on the struct applies the extra uniform buffer rules:
+//   nested structs and arrays are aligned to 16, the member after a nested
+//   struct starts at least `roundUp(16, size)` after it, and array strides
+//   and nested structs that break the rules fail to compile
+#[proc_macro_derive(ToWgslShaderSlice, attributes(wgsl))]
+pub fn derive_to_wgsl_shader_slice(item: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(item as DeriveInput);
+
+    match expand(input) {
+        Ok(out) => out.into(),
+        Err(e) => e.to_compile_error().into()
     }
 }
 
-#[proc_macro_derive(ToWgslShaderSlice)]
-pub fn derive_to_wgsl_shader_slice(item: TokenStream) -> TokenStream {
-    let input = syn::parse_macro_input!(item as syn::DeriveInput);
+#[derive(Default)]
+struct Attributes {
+    name: Option<String>,
+    skip: bool,
+    transpose: bool,
+    uniform: bool,
+}
+
+fn parse_attributes(attrs: &[syn::Attribute]) -> syn::Result<Attributes> {
+    let mut out = Attributes::default();
 
-    let struct_idendifier = input.ident;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("wgsl")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                out.name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("skip") {
+                out.skip = true;
+            } else if meta.path.is_ident("transpose") {
+                out.transpose = true;
+            } else if meta.path.is_ident("uniform") {
+                out.uniform = true;
+            } else {
+                return Err(meta.error("unknown wgsl attribute"));
+            }
 
-    Vec
+            Ok(())
+        })?;
+    }
 
-    match input.data {
-        #[automaticly_derived]
-        syn::Data::Struct(syn::DataStruct{ fields, .. }) => {
-            quote! {
-                impl From<#struct_idendifier> for &[u8] {
-                    fn from()
+    Ok(out)
+}
+
+enum Kind {
+    Scalar(&'static str),
+    Vector(&'static str, usize),
+    Matrix { columns: usize, rows: usize, transpose: bool },
+    Array(Box<Kind>, TokenStream2),
+    Struct(Type),
+}
+
+impl Kind {
+    fn parse(ty: &Type) -> syn::Result<Kind> {
+        match ty {
+            Type::Path(path) if path.qself.is_none() => {
+                let scalar = ["f32", "u32", "i32"].into_iter()
+                    .find(|scalar| path.path.is_ident(scalar));
+
+                Ok(match scalar {
+                    Some(scalar) => Kind::Scalar(scalar),
+                    None => Kind::Struct(ty.clone()),
+                })
+            },
+
+            Type::Array(array) => {
+                let elem = Kind::parse(&array.elem)?;
+                let len = literal_len(&array.len);
+
+                Ok(match (elem, len) {
+                    (Kind::Scalar(scalar), Some(len @ 2..=4)) => Kind::Vector(scalar, len),
+                    (Kind::Vector("f32", rows), Some(columns @ 2..=4)) => Kind::Matrix { columns, rows, transpose: false },
+                    (elem, _) => {
+                        let len = &array.len;
+
+                        Kind::Array(Box::new(elem), quote!((#len)))
+                    }
+                })
+            },
+
+            Type::Paren(paren) => Kind::parse(&paren.elem),
+
+            _ => Err(syn::Error::new_spanned(ty, "type has no WGSL layout"))
+        }
+    }
+
+    fn vector_align(len: usize) -> usize {
+        if len == 2 { 8 } else { 16 }
+    }
+
+    fn vector_size(len: usize) -> usize {
+        len * 4
+    }
+
+    fn align(&self) -> TokenStream2 {
+        match self {
+            Kind::Scalar(_) => quote!(4usize),
+            Kind::Vector(_, len) => {
+                let align = Self::vector_align(*len);
+
+                quote!(#align)
+            },
+            Kind::Matrix { rows, .. } => {
+                let align = Self::vector_align(*rows);
+
+                quote!(#align)
+            },
+            Kind::Array(elem, _) => elem.align(),
+            Kind::Struct(ty) => quote!(<#ty>::WGSL_ALIGN),
+        }
+    }
+
+    fn size(&self) -> TokenStream2 {
+        match self {
+            Kind::Scalar(_) => quote!(4usize),
+            Kind::Vector(_, len) => {
+                let size = Self::vector_size(*len);
+
+                quote!(#size)
+            },
+            Kind::Matrix { columns, rows, .. } => {
+                let size = columns * round_up(Self::vector_align(*rows), Self::vector_size(*rows));
+
+                quote!(#size)
+            },
+            Kind::Array(elem, len) => {
+                let stride = elem.stride();
+
+                quote!((#stride * #len))
+            },
+            Kind::Struct(ty) => quote!(<#ty>::WGSL_SIZE),
+        }
+    }
+
+    fn stride(&self) -> TokenStream2 {
+        const_round_up(self.align(), self.size())
+    }
+
+    fn wgsl_type(&self) -> TokenStream2 {
+        match self {
+            Kind::Scalar(scalar) => quote!(::std::string::String::from(#scalar)),
+            Kind::Vector(scalar, len) => {
+                let name = format!("vec{}<{}>", len, scalar);
+
+                quote!(::std::string::String::from(#name))
+            },
+            Kind::Matrix { columns, rows, .. } => {
+                let name = format!("mat{}x{}<f32>", columns, rows);
+
+                quote!(::std::string::String::from(#name))
+            },
+            Kind::Array(elem, len) => {
+                let elem = elem.wgsl_type();
+
+                quote!(::std::format!("array<{}, {}>", #elem, #len))
+            },
+            Kind::Struct(ty) => quote!(::std::string::String::from(<#ty>::WGSL_NAME)),
+        }
+    }
+
+    // Appends `value` to `out`, which has to end at the offset of the value.
+    fn write(&self, value: TokenStream2) -> TokenStream2 {
+        match self {
+            Kind::Scalar(_) => quote! {
+                out.extend_from_slice(&(#value).to_le_bytes());
+            },
+
+            Kind::Vector(..) => quote! {
+                for x in &#value {
+                    out.extend_from_slice(&x.to_le_bytes());
                 }
-            }
-        },
+            },
+
+            Kind::Matrix { columns, rows, transpose } => {
+                let stride = round_up(Self::vector_align(*rows), Self::vector_size(*rows));
+                let element = if *transpose {
+                    quote!(#value[row][column])
+                } else {
+                    quote!(#value[column][row])
+                };
+
+                quote! {
+                    for column in 0..#columns {
+                        let column_start = out.len();
+
+                        for row in 0..#rows {
+                            out.extend_from_slice(&(#element).to_le_bytes());
+                        }
+
+                        out.resize(column_start + #stride, 0);
+                    }
+                }
+            },
+
+            Kind::Array(elem, _) => {
+                let stride = elem.stride();
+                let write = elem.write(quote!((*element)));
 
-        _ => unimplemented!()
+                quote! {
+                    for element in &#value {
+                        let element_start = out.len();
+
+                        #write
+
+                        out.resize(element_start + #stride, 0);
+                    }
+                }
+            },
+
+            Kind::Struct(_) => quote! {
+                (#value).write_wgsl_bytes(out);
+            },
+        }
     }
+}
+
+impl Kind {
+    // Reads the value at `offset` of `bytes`, `depth` keeps the index names of
+    // nested arrays apart.
+    fn read(&self, offset: TokenStream2, depth: usize) -> TokenStream2 {
+        let scalar_at = |scalar: &str, offset: TokenStream2| {
+            let scalar = format_ident!("{}", scalar);
+
+            quote!(#scalar::from_le_bytes(bytes[#offset..#offset + 4].try_into().unwrap()))
+        };
+
+        match self {
+            Kind::Scalar(scalar) => scalar_at(scalar, offset),
+
+            Kind::Vector(scalar, _) => {
+                let read = scalar_at(scalar, quote!((#offset + i * 4)));
+
+                quote!(::std::array::from_fn(|i| #read))
+            },
+
+            Kind::Matrix { rows, transpose, .. } => {
+                let stride = round_up(Self::vector_align(*rows), Self::vector_size(*rows));
+                let read = scalar_at("f32", quote!((#offset + column * #stride + row * 4)));
+
+                if *transpose {
+                    quote!(::std::array::from_fn(|row| ::std::array::from_fn(|column| #read)))
+                } else {
+                    quote!(::std::array::from_fn(|column| ::std::array::from_fn(|row| #read)))
+                }
+            },
+
+            Kind::Array(elem, _) => {
+                let stride = elem.stride();
+                let index = format_ident!("i{}", depth);
+                let read = elem.read(quote!((#offset + #index * #stride)), depth + 1);
+
+                quote!(::std::array::from_fn(|#index| #read))
+            },
+
+            Kind::Struct(ty) => quote!(<#ty>::from_wgsl_bytes(&bytes[#offset..])),
+        }
+    }
+
+    // Whether the value can be placed in a uniform buffer as it is laid out,
+    // the alignment of the member itself is checked by the struct.
+    fn uniform_layout(&self) -> TokenStream2 {
+        match self {
+            Kind::Scalar(_) | Kind::Vector(..) | Kind::Matrix { .. } => quote!(true),
+            Kind::Array(elem, _) => {
+                let stride = elem.stride();
+                let elem = elem.uniform_layout();
+
+                quote!((#stride % 16 == 0 && #elem))
+            },
+            Kind::Struct(ty) => quote!(<#ty>::WGSL_UNIFORM_LAYOUT),
+        }
+    }
+}
+
+fn literal_len(len: &Expr) -> Option<usize> {
+    match len {
+        Expr::Lit(syn::ExprLit { lit: Lit::Int(int), .. }) => int.base10_parse().ok(),
+        _ => None
+    }
+}
+
+fn round_up(align: usize, value: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+fn const_round_up(align: TokenStream2, value: TokenStream2) -> TokenStream2 {
+    quote!({
+        let align: usize = #align;
 
-    todo!()
-}
\ No newline at end of file
+        (#value).div_ceil(align) * align
+    })
+}
+
+fn const_max(a: TokenStream2, b: TokenStream2) -> TokenStream2 {
+    quote!({
+        let a = #a;
+        let b = #b;
+
+        if a > b { a } else { b }
+    })
+}
+
+struct Field {
+    ident: syn::Ident,
+    name: String,
+    kind: Kind,
+    offset: syn::Ident,
+    align_16: bool,
+    // Nested structs of a uniform struct take at least `roundUp(16, size)`.
+    size_16: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let attributes = parse_attributes(&input.attrs)?;
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "generic structs have no WGSL layout"));
+    }
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(ident, "only structs have a WGSL layout"));
+    };
+
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new_spanned(ident, "only structs with named fields have a WGSL layout"));
+    };
+
+    let mut fields = vec![];
+    let mut skipped = vec![];
+
+    for field in &named.named {
+        let field_attributes = parse_attributes(&field.attrs)?;
+
+        if field_attributes.skip {
+            skipped.push(field.ident.clone().unwrap());
+            continue;
+        }
+
+        let field_ident = field.ident.clone().unwrap();
+
+        let kind = match Kind::parse(&field.ty)? {
+            Kind::Matrix { columns, rows, .. } if field_attributes.transpose => {
+                Kind::Matrix { columns: rows, rows: columns, transpose: true }
+            },
+            _ if field_attributes.transpose => {
+                return Err(syn::Error::new_spanned(&field.ty, "only matrices can be transposed"));
+            },
+            kind => kind
+        };
+
+        fields.push(Field {
+            name: field_attributes.name.unwrap_or_else(|| field_ident.to_string()),
+            offset: format_ident!("WGSL_OFFSET_{}", field_ident.to_string().to_uppercase()),
+            align_16: attributes.uniform && matches!(kind, Kind::Array(..) | Kind::Struct(_)),
+            size_16: attributes.uniform && matches!(kind, Kind::Struct(_)),
+            ident: field_ident,
+            kind,
+        });
+    }
+
+    if fields.is_empty() {
+        return Err(syn::Error::new_spanned(ident, "WGSL structs need at least one member"));
+    }
+
+    let wgsl_name = attributes.name.unwrap_or_else(|| ident.to_string());
+
+    let field_align = |field: &Field| {
+        let align = field.kind.align();
+
+        if field.align_16 {
+            const_max(align, quote!(16usize))
+        } else {
+            align
+        }
+    };
+
+    let field_size = |field: &Field| {
+        let size = field.kind.size();
+
+        if field.size_16 {
+            const_round_up(quote!(16usize), size)
+        } else {
+            size
+        }
+    };
+
+    let mut offsets = vec![];
+    let mut end = quote!(0usize);
+    let mut struct_align = quote!(1usize);
+    let mut uniform_layout = vec![];
+    let mut previous_struct: Option<&Field> = None;
+
+    for field in &fields {
+        let offset = &field.offset;
+        let align = field_align(field);
+        let size = field_size(field);
+        let value = const_round_up(align.clone(), end);
+
+        offsets.push(quote! {
+            pub const #offset: usize = #value;
+        });
+
+        if matches!(field.kind, Kind::Array(..) | Kind::Struct(_)) {
+            let layout = field.kind.uniform_layout();
+
+            uniform_layout.push(quote!((Self::#offset % 16 == 0 && #layout)));
+        }
+
+        if let Some(previous) = previous_struct {
+            let previous_offset = &previous.offset;
+            let previous_size = const_round_up(quote!(16usize), previous.kind.size());
+
+            uniform_layout.push(quote!((Self::#offset >= Self::#previous_offset + #previous_size)));
+        }
+
+        previous_struct = matches!(field.kind, Kind::Struct(_)).then_some(field);
+
+        end = quote!((Self::#offset + #size));
+        struct_align = const_max(struct_align, align);
+    }
+
+    let size = const_round_up(quote!(Self::WGSL_ALIGN), end);
+
+    let uniform_checks = fields.iter()
+        .filter(|field| attributes.uniform && matches!(field.kind, Kind::Array(..) | Kind::Struct(_)))
+        .map(|field| {
+            let layout = field.kind.uniform_layout();
+            let message = format!(
+                "`{}::{}` breaks the uniform buffer rules, array strides must be multiples of 16 and nested structs must follow the rules too",
+                ident, field.ident
+            );
+
+            quote! {
+                const _: () = assert!(#layout, #message);
+            }
+        });
+
+    let member_layouts = fields.iter()
+        .map(|field| {
+            let name = &field.name;
+            let offset = &field.offset;
+            let size = field.kind.size();
+
+            quote!((#name, Self::#offset, #size))
+        });
+
+    let members = fields.iter()
+        .map(|field| {
+            let align = if field.align_16 { "@align(16) " } else { "" };
+            let name = format!("{}: ", field.name);
+            let ty = field.kind.wgsl_type();
+
+            let size = field.size_16.then(|| {
+                let size = field_size(field);
+
+                quote!(out.push_str(&::std::format!("@size({}) ", #size));)
+            });
+
+            quote! {
+                out.push_str("    ");
+                out.push_str(#align);
+                #size
+                out.push_str(#name);
+                out.push_str(&#ty);
+                out.push_str(",\n");
+            }
+        });
+
+    let writes = fields.iter()
+        .map(|field| {
+            let field_ident = &field.ident;
+            let offset = &field.offset;
+            let write = field.kind.write(quote!(self.#field_ident));
+
+            quote! {
+                out.resize(start + Self::#offset, 0);
+                #write
+            }
+        });
+
+    let reads = fields.iter()
+        .map(|field| {
+            let field_ident = &field.ident;
+            let offset = &field.offset;
+            let read = field.kind.read(quote!(Self::#offset), 0);
+
+            quote!(#field_ident: #read,)
+        });
+
+    Ok(quote! {
+        impl #ident {
+            pub const WGSL_NAME: &'static str = #wgsl_name;
+            pub const WGSL_ALIGN: usize = #struct_align;
+            pub const WGSL_SIZE: usize = #size;
+
+            #(#offsets)*
+
+            pub const WGSL_MEMBERS: &'static [(&'static str, usize, usize)] = &[#(#member_layouts),*];
+            pub const WGSL_UNIFORM_LAYOUT: bool = true #(&& #uniform_layout)*;
+
+            pub fn wgsl_struct() -> ::std::string::String {
+                let mut out = ::std::format!("struct {} {{\n", Self::WGSL_NAME);
+
+                #(#members)*
+
+                out.push_str("}\n");
+                out
+            }
+
+            pub fn write_wgsl_bytes(&self, out: &mut ::std::vec::Vec<u8>) {
+                let start = out.len();
+
+                #(#writes)*
+
+                out.resize(start + Self::WGSL_SIZE, 0);
+            }
+
+            pub fn to_wgsl_bytes(&self) -> ::std::vec::Vec<u8> {
+                let mut out = ::std::vec::Vec::with_capacity(Self::WGSL_SIZE);
+
+                self.write_wgsl_bytes(&mut out);
+
+                out
+            }
+
+            // Panics if `bytes` is shorter than `WGSL_SIZE`.
+            pub fn from_wgsl_bytes(bytes: &[u8]) -> Self {
+                Self {
+                    #(#reads)*
+                    #(#skipped: ::std::default::Default::default(),)*
+                }
+            }
+        }
+
+        #(#uniform_checks)*
+    })
+}
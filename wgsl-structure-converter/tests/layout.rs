@@ -0,0 +1,210 @@
+// Most fields only exist for their layout.
+#![allow(dead_code)]
+
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use wgsl_structure_converter::ToWgslShaderSlice;
+
+#[derive(ToWgslShaderSlice)]
+struct Vectors {
+    a: [f32; 3],
+    b: f32,
+    c: [f32; 2],
+    d: [u32; 3],
+}
+
+#[derive(ToWgslShaderSlice)]
+struct Matrices {
+    a: f32,
+    m: [[f32; 3]; 3],
+    #[wgsl(transpose)]
+    t: [[f32; 2]; 3],
+    b: f32,
+}
+
+#[derive(ToWgslShaderSlice)]
+struct Inner {
+    a: [f32; 3],
+    b: u32,
+    c: f32,
+}
+
+#[derive(ToWgslShaderSlice)]
+struct Small {
+    a: f32,
+    b: f32,
+    c: f32,
+}
+
+#[derive(ToWgslShaderSlice)]
+struct Outer {
+    a: f32,
+    inner: Inner,
+    b: f32,
+    small: Small,
+    list: [f32; 5],
+}
+
+#[derive(ToWgslShaderSlice)]
+#[wgsl(uniform)]
+struct UniformOuter {
+    a: f32,
+    small: Small,
+    b: f32,
+    list: [[f32; 4]; 2],
+}
+
+#[derive(ToWgslShaderSlice)]
+struct Scalars {
+    a: [f32; 2],
+}
+
+// Scalars has a stride of 8, but its own layout is fine for a uniform buffer.
+#[derive(ToWgslShaderSlice)]
+struct ScalarList {
+    list: [Scalars; 2],
+}
+
+// Only breaks the rule for the member after a nested struct.
+#[derive(ToWgslShaderSlice)]
+struct AfterSmall {
+    small: Small,
+    b: f32,
+}
+
+fn members<const N: usize>(declarations: &str, name: &str) -> (u32, [(String, u32); N]) {
+    let module = naga::front::wgsl::parse_str(declarations)
+        .unwrap_or_else(|e| panic!("{}\n{}", e.emit_to_string(declarations), declarations));
+
+    let (_, ty) = module.types.iter()
+        .find(|(_, ty)| ty.name.as_deref() == Some(name))
+        .unwrap();
+
+    let naga::TypeInner::Struct { members, span } = &ty.inner else { unreachable!() };
+
+    let members = std::array::from_fn(|i| (members[i].name.clone().unwrap(), members[i].offset));
+
+    (*span, members)
+}
+
+fn rust_members<const N: usize>(members: &[(&str, usize, usize)]) -> [(String, u32); N] {
+    std::array::from_fn(|i| (members[i].0.to_string(), members[i].1 as u32))
+}
+
+fn validate_uniform(declarations: &str, name: &str) -> bool {
+    let source = format!("{}\n@group(0) @binding(0) var<uniform> u: {};\n", declarations, name);
+    let module = naga::front::wgsl::parse_str(&source).unwrap();
+
+    Validator::new(ValidationFlags::all(), Capabilities::all()).validate(&module).is_ok()
+}
+
+#[test]
+fn vectors_match_naga() {
+    assert_eq!(Vectors::WGSL_MEMBERS.iter().map(|m| m.1).collect::<Vec<_>>(), [0, 12, 16, 32]);
+    assert_eq!(Vectors::WGSL_SIZE, 48);
+
+    let (size, members) = members::<4>(&Vectors::wgsl_struct(), "Vectors");
+
+    assert_eq!(size as usize, Vectors::WGSL_SIZE);
+    assert_eq!(members, rust_members(Vectors::WGSL_MEMBERS));
+}
+
+#[test]
+fn matrices_match_naga() {
+    assert_eq!(Matrices::WGSL_OFFSET_M, 16);
+    assert_eq!(Matrices::WGSL_OFFSET_T, 64);
+    assert_eq!(Matrices::WGSL_OFFSET_B, 96);
+
+    let (size, members) = members::<4>(&Matrices::wgsl_struct(), "Matrices");
+
+    assert_eq!(size as usize, Matrices::WGSL_SIZE);
+    assert_eq!(members, rust_members(Matrices::WGSL_MEMBERS));
+}
+
+#[test]
+fn matrix_bytes_are_columns() {
+    let value = Matrices {
+        a: 0.0,
+        m: [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]],
+        t: [[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]],
+        b: 10.0,
+    };
+
+    let bytes = value.to_wgsl_bytes();
+    let float = |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+    assert_eq!(bytes.len(), Matrices::WGSL_SIZE);
+    assert_eq!([float(16), float(20), float(24), float(32)], [1.0, 2.0, 3.0, 4.0]);
+    // `t` is stored by rows, so its columns are 1, 3, 5 and 2, 4, 6.
+    assert_eq!([float(64), float(68), float(72), float(80)], [1.0, 3.0, 5.0, 2.0]);
+    assert_eq!(float(96), 10.0);
+
+    let back = Matrices::from_wgsl_bytes(&bytes);
+
+    assert_eq!((back.m, back.t, back.b), (value.m, value.t, value.b));
+}
+
+#[test]
+fn nested_struct_matches_naga() {
+    let declarations = Inner::wgsl_struct() + &Small::wgsl_struct() + &Outer::wgsl_struct();
+
+    assert_eq!(Outer::WGSL_OFFSET_INNER, 16);
+    assert_eq!(Outer::WGSL_OFFSET_B, 48);
+    assert_eq!(Outer::WGSL_OFFSET_SMALL, 52);
+    assert_eq!(Outer::WGSL_OFFSET_LIST, 64);
+
+    let (size, members) = members::<5>(&declarations, "Outer");
+
+    assert_eq!(size as usize, Outer::WGSL_SIZE);
+    assert_eq!(members, rust_members(Outer::WGSL_MEMBERS));
+}
+
+#[test]
+fn storage_layouts_are_not_uniform() {
+    const { assert!(Small::WGSL_UNIFORM_LAYOUT) };
+
+    let declarations = Inner::wgsl_struct() + &Small::wgsl_struct() + &Outer::wgsl_struct();
+
+    const { assert!(Inner::WGSL_UNIFORM_LAYOUT) };
+    const { assert!(!Outer::WGSL_UNIFORM_LAYOUT) };
+    assert!(!validate_uniform(&declarations, "Outer"));
+
+    const { assert!(!AfterSmall::WGSL_UNIFORM_LAYOUT) };
+    assert!(!validate_uniform(&(Small::wgsl_struct() + &AfterSmall::wgsl_struct()), "AfterSmall"));
+
+    const { assert!(!ScalarList::WGSL_UNIFORM_LAYOUT) };
+    assert!(!validate_uniform(&(Scalars::wgsl_struct() + &ScalarList::wgsl_struct()), "ScalarList"));
+}
+
+#[test]
+fn uniform_layout_matches_naga() {
+    let declarations = Small::wgsl_struct() + &UniformOuter::wgsl_struct();
+
+    // The member after a nested struct starts `roundUp(16, 12)` after it.
+    assert_eq!(UniformOuter::WGSL_OFFSET_SMALL, 16);
+    assert_eq!(UniformOuter::WGSL_OFFSET_B, 32);
+    assert_eq!(UniformOuter::WGSL_OFFSET_LIST, 48);
+    const { assert!(UniformOuter::WGSL_UNIFORM_LAYOUT) };
+
+    let (size, members) = members::<4>(&declarations, "UniformOuter");
+
+    assert_eq!(size as usize, UniformOuter::WGSL_SIZE);
+    assert_eq!(members, rust_members(UniformOuter::WGSL_MEMBERS));
+    assert!(validate_uniform(&declarations, "UniformOuter"));
+}
+
+#[test]
+fn uniform_bytes_skip_nested_struct_padding() {
+    let value = UniformOuter {
+        a: 1.0,
+        small: Small { a: 2.0, b: 3.0, c: 4.0 },
+        b: 7.0,
+        list: [[8.0; 4], [9.0; 4]],
+    };
+
+    let bytes = value.to_wgsl_bytes();
+    let back = UniformOuter::from_wgsl_bytes(&bytes);
+
+    assert_eq!(bytes.len(), UniformOuter::WGSL_SIZE);
+    assert_eq!(f32::from_le_bytes(bytes[32..36].try_into().unwrap()), 7.0);
+    assert_eq!((back.small.a, back.small.c, back.b, back.list), (2.0, 4.0, 7.0, value.list));
+}